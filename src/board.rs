@@ -32,6 +32,13 @@ const DEFAULT_PIECES: [u64; 12] = [
     0x1000000000000000, //bk 11
 ];
 
+// the rook squares of each castle right in standard chess, indexed by the castle right
+pub const DEFAULT_CASTLE_ROOKS: [u8; 4] = [7, 0, 63, 56];
+
+// where the king and rook end up after castling, these are the same in chess960
+pub const CASTLE_KING_TO: [usize; 4] = [6, 2, 62, 58];
+pub const CASTLE_ROOK_TO: [usize; 4] = [5, 3, 61, 59];
+
 const DEFAULT_UTIL: [u64; 3] = [
     0x000000000000FFFF, // white
     0xFFFF000000000000, // black
@@ -45,6 +52,8 @@ pub struct Board {
     pub(super) util: [u64; 3],
     pub(super) ctm: u8,
     pub(super) castle_state: u8,
+    // the starting squares of the castling rooks, which can be anywhere on the back rank in chess960
+    pub(super) castle_rooks: [u8; 4],
     pub(super) ep: u8,
     pub(super) halfmove: u16,
     pub(super) hash: u64,
@@ -65,6 +74,7 @@ impl Board {
             util: DEFAULT_UTIL,
             ctm: WHITE as u8,
             castle_state: 0b1111,
+            castle_rooks: DEFAULT_CASTLE_ROOKS,
             ep: 64,
             halfmove: 0,
             hash: 0,
@@ -154,6 +164,11 @@ impl Board {
         self.castle_state
     }
 
+    #[inline]
+    pub fn castle_rook(&self, castle_idx: usize) -> usize {
+        self.castle_rooks[castle_idx] as usize
+    }
+
     #[inline]
     pub fn hash(&self) -> u64 {
         self.hash
@@ -179,14 +194,31 @@ impl Board {
         let from_to = SQUARES[from] | SQUARES[to];
 
         let mut board = *self;
-        board.set_pieces(piece, from_to);
-        board.set_util(from_to);
+
+        // castles move the king and rook together in apply_castle
+        if move_type.castle_idx().is_none() {
+            board.set_pieces(piece, from_to);
+            board.set_util(from_to);
+            board.set_hash(piece, from, to);
+            board.set_values(piece, from, to);
+        }
+
         board.set_castle_state(piece, from, to);
-        board.set_hash(piece, from, to);
-        board.set_values(piece, from, to);
-        board.ep = 64;
+        board.clear_ep();
+        board.halfmove += 1;
+        board.apply_move(from, to, piece, xpiece, move_type);
+        board.hash ^= Zorb::colour();
+        board.ctm ^= 1;
+
+        board
+    }
+
+    // passes the turn to the other side without moving, this is only used by null move pruning
+    pub fn copy_make_null(&self) -> Board {
+        let mut board = *self;
+
+        board.clear_ep();
         board.halfmove += 1;
-        board.apply_move(to, piece, xpiece, move_type);
         board.hash ^= Zorb::colour();
         board.ctm ^= 1;
 
@@ -203,9 +235,12 @@ impl Board {
     }
 
     fn set_hash(&mut self, piece: usize, from: usize, to: usize) {
-        self.hash ^= Zorb::piece(piece, from)
-            ^ Zorb::piece(piece, to)
-            ^ ((self.ep < 64) as u64 * Zorb::ep_file(self.ep()))
+        self.hash ^= Zorb::piece(piece, from) ^ Zorb::piece(piece, to)
+    }
+
+    fn clear_ep(&mut self) {
+        self.hash ^= (self.ep < 64) as u64 * Zorb::ep_file(self.ep());
+        self.ep = 64;
     }
 
     fn set_values(&mut self, piece: usize, from: usize, to: usize) {
@@ -216,36 +251,45 @@ impl Board {
     pub fn set_castle_state(&mut self, piece: usize, from: usize, to: usize) {
         // stop thinking you can optimise this
 
-        if (piece == 10 || from == 7 || to == 7) && self.castle_state & 0b1000 > 0 {
+        let [wks, wqs, bks, bqs] = self.castle_rooks.map(usize::from);
+
+        if (piece == 10 || from == wks || to == wks) && self.castle_state & 0b1000 > 0 {
             self.castle_state &= 0b0111;
             self.hash ^= Zorb::castle_rights(WKS_STATE);
         }
 
-        if (piece == 10 || from == 0 || to == 0) && self.castle_state & 0b100 > 0 {
+        if (piece == 10 || from == wqs || to == wqs) && self.castle_state & 0b100 > 0 {
             self.castle_state &= 0b1011;
             self.hash ^= Zorb::castle_rights(WQS_STATE);
         }
 
-        if (piece == 11 || from == 63 || to == 63) && self.castle_state & 0b10 > 0 {
+        if (piece == 11 || from == bks || to == bks) && self.castle_state & 0b10 > 0 {
             self.castle_state &= 0b1101;
             self.hash ^= Zorb::castle_rights(BKS_STATE);
         }
 
-        if (piece == 11 || from == 56 || to == 56) && self.castle_state & 0b1 > 0 {
+        if (piece == 11 || from == bqs || to == bqs) && self.castle_state & 0b1 > 0 {
             self.castle_state &= 0b1110;
             self.hash ^= Zorb::castle_rights(BQS_STATE);
         }
     }
 
-    fn apply_move(&mut self, to: usize, piece: usize, xpiece: usize, move_type: MoveType) {
+    fn apply_move(
+        &mut self,
+        from: usize,
+        to: usize,
+        piece: usize,
+        xpiece: usize,
+        move_type: MoveType,
+    ) {
         match move_type {
             MoveType::Quiet => self.apply_quiet(piece),
             MoveType::Double => self.apply_double(to),
             MoveType::Cap => self.apply_cap(xpiece, to),
-            MoveType::WKingSide => self.apply_castle(0, 7, 5),
-            MoveType::BKingSide => self.apply_castle(1, 63, 61),
-            MoveType::WQueenSide => self.apply_castle(0, 0, 3),
-            MoveType::BQueenSide => self.apply_castle(1, 56, 59),
+            MoveType::WKingSide => self.apply_castle(piece, from, to, WKS_STATE),
+            MoveType::BKingSide => self.apply_castle(piece, from, to, BKS_STATE),
+            MoveType::WQueenSide => self.apply_castle(piece, from, to, WQS_STATE),
+            MoveType::BQueenSide => self.apply_castle(piece, from, to, BQS_STATE),
             MoveType::Promo => self.apply_promo(piece, xpiece, to),
             MoveType::NPromoCap
             | MoveType::RPromoCap
@@ -276,17 +320,27 @@ impl Board {
         self.halfmove = 0;
     }
 
-    fn apply_castle(&mut self, colour: usize, from: usize, to: usize) {
-        let sqs = SQUARES[from] | SQUARES[to];
-        self.pieces[ROOK + colour] ^= sqs;
-        self.util[colour] ^= sqs;
-        self.util[ALL_PIECES] ^= sqs;
+    // castles are stored as the king taking its own rook. In chess960 the king and rook squares can
+    // overlap, so both pieces are lifted off the board before either is put back down
+    fn apply_castle(&mut self, king: usize, from: usize, rook_from: usize, castle_idx: usize) {
+        let rook = ROOK + self.ctm();
+
+        self.toggle_piece(king, from);
+        self.toggle_piece(rook, rook_from);
+        self.toggle_piece(king, CASTLE_KING_TO[castle_idx]);
+        self.toggle_piece(rook, CASTLE_ROOK_TO[castle_idx]);
 
-        self.add_piece_value(ROOK + colour, to);
-        self.remove_piece_value(ROOK + colour, from);
+        self.add_piece_value(king, CASTLE_KING_TO[castle_idx]);
+        self.remove_piece_value(king, from);
+        self.add_piece_value(rook, CASTLE_ROOK_TO[castle_idx]);
+        self.remove_piece_value(rook, rook_from);
+    }
 
-        self.toggle_piece_hash(ROOK + colour, to);
-        self.toggle_piece_hash(ROOK + colour, from);
+    fn toggle_piece(&mut self, piece: usize, sq: usize) {
+        self.pieces[piece] ^= SQUARES[sq];
+        self.util[self.ctm()] ^= SQUARES[sq];
+        self.util[ALL_PIECES] ^= SQUARES[sq];
+        self.toggle_piece_hash(piece, sq);
     }
 
     fn apply_promo(&mut self, piece: usize, xpiece: usize, to: usize) {
@@ -376,7 +430,7 @@ impl PartialEq for Board {
             return false;
         }
 
-        if self.castle_state != other.castle_state {
+        if self.castle_state != other.castle_state || self.castle_rooks != other.castle_rooks {
             return false;
         }
 
@@ -577,7 +631,7 @@ fn inc_value_update() {
     assert_eq!(quiet_board.mg_value, mg_quiet);
     assert_eq!(quiet_board.eg_value, eg_quiet);
 
-    let cap_move = Move::new(25, 32, BISHOP as u32, KING as u32 + 1, MoveType::Cap);
+    let cap_move = Move::new(25, 32, BISHOP as u32, KNIGHT as u32 + 1, MoveType::Cap);
     let cap_board = board.copy_make(cap_move);
 
     let mat = gen_mat_value(&cap_board);
@@ -610,8 +664,8 @@ fn test_inc_values_and_hash_copy_make() {
             Board::new_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -")
                 .unwrap(),
             vec![
-                Move::new(4, 2, KING as u32, 0, MoveType::WQueenSide),
-                Move::new(4, 6, KING as u32, 0, MoveType::WKingSide),
+                Move::new(4, 0, KING as u32, 0, MoveType::WQueenSide),
+                Move::new(4, 7, KING as u32, 0, MoveType::WKingSide),
             ],
         ),
         (
@@ -619,8 +673,8 @@ fn test_inc_values_and_hash_copy_make() {
             Board::new_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq -")
                 .unwrap(),
             vec![
-                Move::new(60, 58, KING as u32 + 1, 0, MoveType::BQueenSide),
-                Move::new(60, 62, KING as u32 + 1, 0, MoveType::BKingSide),
+                Move::new(60, 56, KING as u32 + 1, 0, MoveType::BQueenSide),
+                Move::new(60, 63, KING as u32 + 1, 0, MoveType::BKingSide),
             ],
         ),
         (
@@ -671,3 +725,16 @@ fn add_rm_piece_value() {
     assert_eq!(b.mg_value, 0 - 100 - -35 + 100 + -26);
     assert_eq!(b.eg_value, 0 - 100 - 13 + 100 + 4);
 }
+
+#[test]
+fn null_move_flips_side_and_hash() {
+    crate::init();
+
+    let board = Board::new_fen("8/8/3p4/KPp4r/4Pp1k/8/6P1/1R6 b - e3 0 2").unwrap();
+    let null = board.copy_make_null();
+
+    assert_eq!(null.ctm(), WHITE);
+    assert_eq!(null.ep(), 64);
+    assert_eq!(null.hash(), gen_hash(null));
+    assert_eq!(null.pieces, board.pieces);
+}
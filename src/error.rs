@@ -14,6 +14,8 @@ pub enum InvalidFenError {
     InvalidEpSquare,
     #[error("Invalid HalfMove")]
     InvalidHalfmove,
+    #[error("Side Not To Move In Check")]
+    OppInCheck,
 }
 
 #[derive(Error, Debug)]
@@ -38,4 +40,33 @@ pub enum InvalidUciCommand {
     UnknownCommand(String),
     #[error("Invalid position: {0}")]
     InvalidPositionCommand(String),
+    #[error("Invalid go: {0}")]
+    InvalidGoCommand(String),
+    #[error("Invalid setoption: {0}")]
+    InvalidOption(String),
+    #[error("Unknown option: {0}")]
+    UnknownOption(String),
+    #[error("Invalid value for option {0}: {1}")]
+    InvalidOptionValue(String, String),
+    #[error("Invalid depth for debug command: {0}")]
+    InvalidDebugCommand(String),
+    #[error("Invalid move: {0}")]
+    InvalidMove(String),
+    #[error("Illegal move: {0}")]
+    IllegalMove(String),
+    #[error("Invalid fen {0}: {1}")]
+    InvalidFen(String, InvalidFenError),
+}
+
+// the messages are the error replies xboard expects
+#[derive(Error, Debug)]
+pub enum InvalidXBoardCommand {
+    #[error("Error (unknown command): {0}")]
+    UnknownCommand(String),
+    #[error("Error (invalid arguments): {0}")]
+    InvalidArgs(String),
+    #[error("Illegal move: {0}")]
+    IllegalMove(String),
+    #[error("tellusererror Illegal position: {0}")]
+    IllegalPosition(String),
 }
@@ -50,8 +50,8 @@ pub const MAT_SCORES: [i32; 12] = [
 const PIECE_PHASE_VAL: [i32; 12] = [0, 0, 1, 1, 1, 1, 2, 2, 4, 4, 0, 0];
 
 // TODO incremental update of game phase
-pub fn eval(board: &Board, colour_mul: i32) -> i32 {
-    let mg_phase = max(
+fn mg_phase(board: &Board) -> i32 {
+    max(
         24,
         board
             .pieces_iter()
@@ -61,8 +61,11 @@ pub fn eval(board: &Board, colour_mul: i32) -> i32 {
             .fold(0, |mg_phase, (p, pieces)| {
                 mg_phase + PIECE_PHASE_VAL[p] * pieces.count_ones() as i32
             }),
-    );
+    )
+}
 
+pub fn eval(board: &Board, colour_mul: i32) -> i32 {
+    let mg_phase = mg_phase(board);
     let eg_phase = 24 - mg_phase;
 
     let eval = (board.mg_value() * mg_phase + board.eg_value() * eg_phase) / 24;
@@ -72,6 +75,21 @@ pub fn eval(board: &Board, colour_mul: i32) -> i32 {
     // board.mg_value() * colour_mul
 }
 
+// the terms that make up the static eval, all from white's point of view
+pub fn eval_trace(board: &Board) -> String {
+    let mat = gen_mat_value(board);
+    let (pst_mg, pst_eg) = gen_pst_value(board);
+
+    [
+        format!("Material:    {mat}"),
+        format!("PST (mg/eg): {pst_mg} / {pst_eg}"),
+        format!("Total:       {} / {}", board.mg_value(), board.eg_value()),
+        format!("Phase:       {} / 24 mg", mg_phase(board)),
+        format!("Eval:        {}", eval(board, 1)),
+    ]
+    .join("\n")
+}
+
 pub fn gen_board_value(board: &Board) -> (i32, i32) {
     board
         .pieces_iter()
@@ -1,7 +1,8 @@
-use crate::board::{gen_hash, Board};
+use crate::board::{gen_hash, Board, BLACK, DEFAULT_CASTLE_ROOKS, KING, PIECE_NAMES, ROOK, WHITE};
 use crate::error::InvalidFenError;
 use crate::eval::gen_board_value;
-use crate::move_info::SQUARES;
+use crate::move_info::{SQUARES, SQ_NAMES};
+use crate::movegen::sq_attacked;
 
 fn piece_from_char(name: char) -> Option<usize> {
     match name {
@@ -38,24 +39,37 @@ fn pieces_from_fen(fen: &str) -> Result<[u64; 12], InvalidFenError> {
 
     let mut pieces = [0; 12];
 
-    // iterates through each rank of the fen from 1-8
-    fen_pieces
-        .split('/')
-        .collect::<Vec<_>>()
-        .iter()
-        .rev()
-        .enumerate()
-        .map(|(i, row)| (i * 8, row))
-        .try_for_each(|(mut idx, row)| {
-            row.chars().take(8).try_for_each(|sq| {
-                if let Some(piece) = piece_from_char(sq) {
-                    pieces[piece] ^= SQUARES[idx];
-                }
+    let ranks: Vec<&str> = fen_pieces.split('/').collect();
+    if ranks.len() != 8 {
+        return Err(InvalidFenError::InvalidPieces);
+    }
 
-                idx += inc_from_char(sq).ok_or(InvalidFenError::InvalidPieces)?;
-                Ok(())
-            })
-        })?;
+    // iterates through each rank of the fen from 1-8, each has to fill exactly its 8 squares
+    for (i, row) in ranks.iter().rev().enumerate() {
+        let end = i * 8 + 8;
+        let mut idx = i * 8;
+
+        for sq in row.chars() {
+            if idx >= end {
+                return Err(InvalidFenError::InvalidPieces);
+            }
+
+            if let Some(piece) = piece_from_char(sq) {
+                pieces[piece] ^= SQUARES[idx];
+            }
+
+            idx += inc_from_char(sq).ok_or(InvalidFenError::InvalidPieces)?;
+        }
+
+        if idx != end {
+            return Err(InvalidFenError::InvalidPieces);
+        }
+    }
+
+    // the move generator relies on each side having exactly one king
+    if pieces[KING].count_ones() != 1 || pieces[KING + BLACK].count_ones() != 1 {
+        return Err(InvalidFenError::InvalidPieces);
+    }
 
     Ok(pieces)
 }
@@ -70,31 +84,64 @@ fn ctm_from_fen(fen: &str) -> Result<u8, InvalidFenError> {
     }
 }
 
-fn castle_state_from_fen(fen: &str) -> Result<u8, InvalidFenError> {
+// reads standard, X-FEN and Shredder-FEN castle fields. K/Q/k/q mean the outermost rook on that
+// side of the king and a file letter (upper case for white) names the castling rook directly, which
+// is needed for chess960. Returns the castle state and the castling rook squares
+fn castle_state_from_fen(fen: &str, pieces: &[u64; 12]) -> Result<(u8, [u8; 4]), InvalidFenError> {
     let castle_state_str = fen
         .split(' ')
         .nth(2)
         .ok_or(InvalidFenError::InvalidCastleState)?;
 
-    match castle_state_str {
-        "KQkq" => Ok(0b1111),
-        "KQk" => Ok(0b1110),
-        "KQq" => Ok(0b1101),
-        "KQ" => Ok(0b1100),
-        "Kkq" => Ok(0b1011),
-        "Kk" => Ok(0b1010),
-        "Kq" => Ok(0b1001),
-        "K" => Ok(0b1000),
-        "Qkq" => Ok(0b0111),
-        "Qk" => Ok(0b0110),
-        "Qq" => Ok(0b0101),
-        "Q" => Ok(0b0100),
-        "kq" => Ok(0b0011),
-        "k" => Ok(0b0010),
-        "q" => Ok(0b0001),
-        "-" => Ok(0b0000),
-        _ => Err(InvalidFenError::InvalidCastleState),
+    let mut castle_state = 0;
+    let mut castle_rooks = DEFAULT_CASTLE_ROOKS;
+
+    if castle_state_str == "-" {
+        return Ok((castle_state, castle_rooks));
     }
+
+    for c in castle_state_str.chars() {
+        let colour = if c.is_ascii_uppercase() { WHITE } else { BLACK };
+        let back_rank = 0xFF << (colour * 56);
+
+        let king = pieces[KING + colour] & back_rank;
+        let rooks = pieces[ROOK + colour] & back_rank;
+        if king == 0 {
+            return Err(InvalidFenError::InvalidCastleState);
+        }
+
+        let king_sq = king.trailing_zeros();
+        let rook = match c.to_ascii_lowercase() {
+            'k' => rooks & !((king << 1) - 1),
+            'q' => rooks & (king - 1),
+            file @ 'a'..='h' => rooks & (1 << (colour * 56 + (file as usize - 'a' as usize))),
+            _ => 0,
+        };
+
+        if rook == 0 {
+            return Err(InvalidFenError::InvalidCastleState);
+        }
+
+        // use the outermost rook when there is more than one on that side
+        let rook_sq = if rook > king {
+            63 - rook.leading_zeros()
+        } else {
+            rook.trailing_zeros()
+        };
+
+        let idx = colour * 2 + (rook_sq < king_sq) as usize;
+        let right = 0b1000 >> idx;
+
+        // each castle right can only be given once
+        if castle_state & right > 0 {
+            return Err(InvalidFenError::InvalidCastleState);
+        }
+
+        castle_state |= right;
+        castle_rooks[idx] = rook_sq as u8;
+    }
+
+    Ok((castle_state, castle_rooks))
 }
 
 fn ep_sq_from_fen(fen: &str) -> Result<u8, InvalidFenError> {
@@ -151,11 +198,14 @@ impl Board {
         let white = pieces[0] | pieces[2] | pieces[4] | pieces[6] | pieces[8] | pieces[10];
         let black = pieces[1] | pieces[3] | pieces[5] | pieces[7] | pieces[9] | pieces[11];
 
+        let (castle_state, castle_rooks) = castle_state_from_fen(fen, &pieces)?;
+
         let mut board = Board {
             pieces,
             util: [white, black, white | black],
             ctm: ctm_from_fen(fen)?,
-            castle_state: castle_state_from_fen(fen)?,
+            castle_state,
+            castle_rooks,
             ep: ep_sq_from_fen(fen)?,
             halfmove: halfmove_from_fen(fen)?,
             hash: 0,
@@ -163,12 +213,89 @@ impl Board {
             eg_value: 0,
         };
 
+        // the side to move could take the king, which the search can't handle
+        if sq_attacked(&board, board.king_idx(board.opp_ctm()), board.ctm()) {
+            return Err(InvalidFenError::OppInCheck);
+        }
+
         // regen the hash after everything is finished
         board.hash = gen_hash(board);
         (board.mg_value, board.eg_value) = gen_board_value(&board);
 
         Ok(board)
     }
+
+    // the move number is not tracked by the board, so it is always given as 1
+    pub fn to_fen(&self) -> String {
+        let ranks: Vec<String> = (0..8)
+            .rev()
+            .map(|rank| {
+                let mut row = String::new();
+                let mut empty = 0;
+
+                for sq in SQUARES.iter().skip(rank * 8).take(8) {
+                    match self.pieces.iter().position(|p| p & sq > 0) {
+                        Some(piece) => {
+                            if empty > 0 {
+                                row.push_str(&empty.to_string());
+                                empty = 0;
+                            }
+                            row.push_str(PIECE_NAMES[piece]);
+                        }
+                        None => empty += 1,
+                    }
+                }
+
+                if empty > 0 {
+                    row.push_str(&empty.to_string());
+                }
+
+                row
+            })
+            .collect();
+
+        let ctm = if self.ctm() == WHITE { "w" } else { "b" };
+        let ep = if self.ep < 64 {
+            SQ_NAMES[self.ep()]
+        } else {
+            "-"
+        };
+
+        format!(
+            "{} {ctm} {} {ep} {} 1",
+            ranks.join("/"),
+            self.castle_state_fen(),
+            self.halfmove
+        )
+    }
+
+    // rooks on their standard squares use KQkq, any other rook is given by its file (shredder fen)
+    fn castle_state_fen(&self) -> String {
+        let castles: String = ["K", "Q", "k", "q"]
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| self.castle_state & (0b1000 >> idx) > 0)
+            .map(|(idx, standard)| {
+                let rook = self.castle_rooks[idx];
+                if rook == DEFAULT_CASTLE_ROOKS[idx] {
+                    standard.to_string()
+                } else {
+                    let file = (b'a' + rook % 8) as char;
+                    if idx < 2 {
+                        file.to_ascii_uppercase().to_string()
+                    } else {
+                        file.to_string()
+                    }
+                }
+            })
+            .collect();
+
+        if castles.is_empty() {
+            "-".into()
+        } else {
+            castles
+        }
+    }
 }
 
 #[test]
@@ -195,8 +322,27 @@ fn test_fens() -> Result<(), InvalidFenError> {
         "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KaQkq c6 0 2",
         "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq r5 1 2 ",
         "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - -1 2 ",
+        "8/8/8/8/8/8/8/8 w - - 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKKNR w - - 0 1",
+        "4k2R/8/8/8/8/8/8/4K3 w - - 0 1",
+        "rnbqkbnr/ppppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN w Qkq - 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
     ];
 
+    let round_trip_fens = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KFkf - 2 1",
+        "8/8/3p4/KPp4r/4Pp1k/8/6P1/1R6 b - e3 12 1",
+    ];
+
+    for fen in round_trip_fens {
+        assert_eq!(Board::new_fen(fen)?.to_fen(), fen);
+    }
+
     for fen in bad_fens {
         println!("{}", fen);
         assert!(Board::new_fen(fen).is_err());
@@ -2,104 +2,192 @@ use std::sync::Arc;
 
 use crate::{
     board::Board,
+    hh::SearchHistory,
     moves::PrevMoves,
-    searcher::{iterative_deepening, lazy_smp, SearchResult},
-    tt::{NoTTable, SmpTTable, TTable, TT},
+    searcher::{iterative_deepening, lazy_smp, SearchOptions, SearchResult},
+    time_manager::{SearchControl, SearchLimits},
+    tt::{NoTTable, SmpTTable, TTable, DEFAULT_HASH_MB, TT},
 };
 
+// bench searches get a table of this size, a full size one would double the memory used and
+// take longer to clear than the searches take
+const BENCH_HASH_MB: usize = 16;
+
 pub trait CanSearch {
     fn new_game(&mut self);
-    fn go(&mut self) -> SearchResult;
+    fn go(&mut self, limits: SearchLimits) -> SearchResult;
+}
+
+// the tt backend is picked at runtime as it depends on the number of threads
+pub enum GameTT {
+    NoTT(NoTTable),
+    Single(TTable),
+    Smp(Arc<SmpTTable>),
+}
+
+impl GameTT {
+    fn clear(&mut self) {
+        match self {
+            GameTT::NoTT(tt) => tt.clear(),
+            GameTT::Single(tt) => (&mut *tt).clear(),
+            GameTT::Smp(tt) => tt.clear(),
+        }
+    }
 }
 
-pub struct GameState<T> {
-    tt: T,
+pub struct GameState {
+    tt: GameTT,
     board: Board,
     prev_moves: PrevMoves,
+    // what the search learnt about quiet moves, kept for the rest of the game
+    history: SearchHistory,
     num_threads: usize,
+    hash_mb: usize,
+    options: SearchOptions,
+    control: SearchControl,
 }
 
-impl GameState<TTable> {
-    pub fn new() -> GameState<TTable> {
+impl GameState {
+    fn with_tt(tt: GameTT, num_threads: usize) -> GameState {
         GameState {
-            tt: TTable::new(),
+            tt,
             board: Board::new(),
             prev_moves: PrevMoves::new(),
-            num_threads: 1,
+            history: SearchHistory::new(),
+            num_threads,
+            hash_mb: DEFAULT_HASH_MB,
+            options: SearchOptions::default(),
+            control: SearchControl::new(),
         }
     }
-}
 
-impl Default for GameState<TTable> {
-    fn default() -> Self {
-        Self::new()
+    pub fn new() -> GameState {
+        GameState::with_tt(GameTT::Single(TTable::new()), 1)
     }
-}
 
-impl GameState<NoTTable> {
-    pub fn new_no_tt() -> GameState<NoTTable> {
-        GameState {
-            tt: NoTTable::default(),
-            board: Board::new(),
-            prev_moves: PrevMoves::new(),
-            num_threads: 1,
-        }
+    pub fn new_no_tt() -> GameState {
+        GameState::with_tt(GameTT::NoTT(NoTTable::default()), 1)
     }
-}
 
-impl GameState<Arc<SmpTTable>> {
-    pub fn new_smp(num_threads: usize) -> GameState<Arc<SmpTTable>> {
-        GameState {
-            tt: SmpTTable::new(),
-            board: Board::new(),
-            prev_moves: PrevMoves::new(),
-            num_threads,
-        }
+    pub fn new_smp(num_threads: usize) -> GameState {
+        GameState::with_tt(GameTT::Smp(SmpTTable::new()), num_threads)
     }
-}
 
-impl<T> GameState<T> {
     pub fn is_ready(&self) -> bool {
         true
     }
 
+    pub fn search_control(&self) -> SearchControl {
+        self.control.clone()
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
     pub fn set_position(&mut self, board: Board, prev_moves: PrevMoves) {
         self.board = board;
         self.prev_moves = prev_moves;
     }
-}
 
-impl CanSearch for GameState<TTable> {
-    fn new_game(&mut self) {
-        (&mut self.tt).clear()
+    pub fn options(&self) -> &SearchOptions {
+        &self.options
     }
 
-    fn go(&mut self) -> SearchResult {
-        iterative_deepening(&self.board, &mut self.tt, self.prev_moves.clone())
+    pub fn options_mut(&mut self) -> &mut SearchOptions {
+        &mut self.options
     }
-}
 
-impl CanSearch for GameState<NoTTable> {
-    fn new_game(&mut self) {
-        self.tt.clear()
+    pub fn set_hash_size(&mut self, hash_mb: usize) {
+        self.hash_mb = hash_mb;
+        self.resize_tt();
+    }
+
+    // switches between the single threaded and smp tt when crossing 1 thread
+    pub fn set_threads(&mut self, num_threads: usize) {
+        let was_smp = self.num_threads > 1;
+        self.num_threads = num_threads;
+
+        if was_smp != (num_threads > 1) {
+            self.resize_tt();
+        }
+    }
+
+    // a new game with the same options, threads and search control. It gets a small tt of its own
+    // whatever the hash size, so a game created without a tt still searches without one
+    pub fn bench_game(&self) -> GameState {
+        let tt = match self.tt {
+            GameTT::NoTT(_) => GameTT::NoTT(NoTTable::default()),
+            GameTT::Single(_) => GameTT::Single(TTable::with_size_mb(BENCH_HASH_MB)),
+            GameTT::Smp(_) => GameTT::Smp(SmpTTable::with_size_mb(BENCH_HASH_MB)),
+        };
+
+        GameState {
+            options: self.options.clone(),
+            control: self.control.clone(),
+            hash_mb: BENCH_HASH_MB,
+            ..GameState::with_tt(tt, self.num_threads)
+        }
     }
 
-    fn go(&mut self) -> SearchResult {
-        iterative_deepening(&self.board, self.tt, self.prev_moves.clone())
+    pub fn clear_hash(&mut self) {
+        self.tt.clear();
+    }
+
+    // a game state created without a tt keeps searching without one
+    fn resize_tt(&mut self) {
+        if let GameTT::NoTT(_) = self.tt {
+            return;
+        }
+
+        // drop the old table before allocating the new one
+        self.tt = GameTT::NoTT(NoTTable::default());
+
+        self.tt = if self.num_threads > 1 {
+            GameTT::Smp(SmpTTable::with_size_mb(self.hash_mb))
+        } else {
+            GameTT::Single(TTable::with_size_mb(self.hash_mb))
+        };
     }
 }
 
-impl CanSearch for GameState<Arc<SmpTTable>> {
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CanSearch for GameState {
     fn new_game(&mut self) {
-        self.tt.clear()
+        self.tt.clear();
+        self.history.clear();
     }
 
-    fn go(&mut self) -> SearchResult {
-        lazy_smp(
-            &self.board,
-            self.tt.clone(),
-            self.prev_moves.clone(),
-            self.num_threads,
-        )
+    fn go(&mut self, limits: SearchLimits) -> SearchResult {
+        let board = &self.board;
+        let prev_moves = self.prev_moves.clone();
+        let options = &self.options;
+        let control = self.control.clone();
+        let history = &mut self.history;
+        history.age();
+
+        match &mut self.tt {
+            GameTT::NoTT(tt) => {
+                iterative_deepening(board, *tt, prev_moves, history, &limits, options, control)
+            }
+            GameTT::Single(tt) => {
+                iterative_deepening(board, tt, prev_moves, history, &limits, options, control)
+            }
+            GameTT::Smp(tt) => lazy_smp(
+                board,
+                tt.clone(),
+                prev_moves,
+                history,
+                &limits,
+                options,
+                control,
+                self.num_threads,
+            ),
+        }
     }
 }
@@ -1,6 +1,24 @@
+use crate::moves::{KillerMoves, Move};
+
+// history scores stay within HISTORY_MAX either way, a cutoff at depth d moves an entry by
+// d * d * HISTORY_BONUS_MUL up to HISTORY_BONUS_MAX
+const HISTORY_MAX: i32 = 16384;
+const HISTORY_BONUS_MUL: i32 = 16;
+const HISTORY_BONUS_MAX: i32 = 1536;
+
+pub fn history_bonus(depth: usize) -> i32 {
+    ((depth * depth) as i32 * HISTORY_BONUS_MUL).min(HISTORY_BONUS_MAX)
+}
+
+// the closer an entry is to the bound the less it moves towards it, so entries never saturate
+// and a new trend overrides an old one quickly
+fn apply_gravity(entry: &mut i32, bonus: i32) {
+    *entry += bonus - *entry * bonus.abs() / HISTORY_MAX;
+}
+
 #[derive(Clone)]
 pub struct HistoryTable {
-    history: Box<[[[u32; 64]; 64]; 2]>,
+    history: Box<[[[i32; 64]; 64]; 2]>,
 }
 
 impl Default for HistoryTable {
@@ -16,11 +34,147 @@ impl HistoryTable {
         HistoryTable::default()
     }
 
-    pub fn insert(&mut self, ctm: usize, from: usize, to: usize, depth: usize) {
-        self.history[ctm][from][to] += (depth * depth) as u32
+    // a negative bonus is a malus
+    pub fn update(&mut self, ctm: usize, from: usize, to: usize, bonus: i32) {
+        apply_gravity(&mut self.history[ctm][from][to], bonus);
     }
 
-    pub fn get(&self, ctm: usize, from: usize, to: usize) -> u32 {
+    pub fn get(&self, ctm: usize, from: usize, to: usize) -> i32 {
         self.history[ctm][from][to]
     }
+
+    fn age(&mut self) {
+        self.history
+            .iter_mut()
+            .flatten()
+            .flatten()
+            .for_each(|h| *h /= 2);
+    }
+}
+
+// the quiet move that last refuted each move, by piece and destination
+#[derive(Clone)]
+pub struct CounterMoves {
+    moves: Box<[[Option<Move>; 64]; 12]>,
+}
+
+impl Default for CounterMoves {
+    fn default() -> Self {
+        CounterMoves {
+            moves: Box::new([[None; 64]; 12]),
+        }
+    }
+}
+
+impl CounterMoves {
+    pub fn new() -> CounterMoves {
+        CounterMoves::default()
+    }
+
+    pub fn insert(&mut self, prev: Move, m: Move) {
+        self.moves[prev.piece() as usize][prev.to() as usize] = Some(m);
+    }
+
+    pub fn get(&self, prev: Move) -> Option<Move> {
+        self.moves[prev.piece() as usize][prev.to() as usize]
+    }
+}
+
+// history of quiet moves following an earlier move, by piece and destination of both
+#[derive(Clone)]
+pub struct ContinuationHistory {
+    // too big for the stack, so a flat [12][64][12][64] table
+    history: Box<[i32]>,
+}
+
+impl Default for ContinuationHistory {
+    fn default() -> Self {
+        ContinuationHistory {
+            history: vec![0; 12 * 64 * 12 * 64].into_boxed_slice(),
+        }
+    }
+}
+
+impl ContinuationHistory {
+    pub fn new() -> ContinuationHistory {
+        ContinuationHistory::default()
+    }
+
+    fn index(prev: Move, m: Move) -> usize {
+        ((prev.piece() as usize * 64 + prev.to() as usize) * 12 + m.piece() as usize) * 64
+            + m.to() as usize
+    }
+
+    // a negative bonus is a malus
+    pub fn update(&mut self, prev: Move, m: Move, bonus: i32) {
+        apply_gravity(&mut self.history[Self::index(prev, m)], bonus);
+    }
+
+    pub fn get(&self, prev: Move, m: Move) -> i32 {
+        self.history[Self::index(prev, m)]
+    }
+
+    fn age(&mut self) {
+        self.history.iter_mut().for_each(|h| *h /= 2);
+    }
+}
+
+// what the search learns about quiet moves, kept between the searches of a game
+#[derive(Clone, Default)]
+pub struct SearchHistory {
+    pub km: KillerMoves,
+    pub hh: HistoryTable,
+    pub cm: CounterMoves,
+    pub ch: ContinuationHistory,
+}
+
+impl SearchHistory {
+    pub fn new() -> SearchHistory {
+        SearchHistory::default()
+    }
+
+    // halves the history before a new search so what was learnt about the last position still
+    // orders moves but is soon outweighed. Killers and countermoves are checked before they are
+    // played, so they are kept as they are
+    pub fn age(&mut self) {
+        self.hh.age();
+        self.ch.age();
+    }
+
+    pub fn clear(&mut self) {
+        *self = SearchHistory::default();
+    }
+}
+
+#[test]
+fn history_is_bounded_and_ages() {
+    use crate::moves::MoveType;
+
+    let m = Move::new(12, 28, 0, 0, MoveType::Quiet);
+    let prev = Move::new(52, 36, 1, 0, MoveType::Quiet);
+    let mut history = SearchHistory::new();
+
+    for _ in 0..1000 {
+        history.hh.update(0, 12, 28, history_bonus(20));
+        history.ch.update(prev, m, -history_bonus(20));
+    }
+
+    assert!((HISTORY_MAX - HISTORY_BONUS_MAX..=HISTORY_MAX).contains(&history.hh.get(0, 12, 28)));
+    assert!((-HISTORY_MAX..=-HISTORY_MAX + HISTORY_BONUS_MAX).contains(&history.ch.get(prev, m)));
+
+    // a malus on a good move pulls it back harder than a bonus pushes it on
+    let good = history.hh.get(0, 12, 28);
+    history.hh.update(0, 12, 28, -history_bonus(4));
+    assert!(good - history.hh.get(0, 12, 28) > history_bonus(4));
+
+    history.cm.insert(prev, m);
+    let before = history.hh.get(0, 12, 28);
+    history.age();
+    assert_eq!(history.hh.get(0, 12, 28), before / 2);
+    assert_eq!(history.cm.get(prev), Some(m));
+
+    history.clear();
+    assert_eq!(history.hh.get(0, 12, 28), 0);
+    assert_eq!(history.ch.get(prev, m), 0);
+    assert_eq!(history.cm.get(prev), None);
 }
@@ -1,12 +1,15 @@
 use crate::board::Board;
 use crate::game_state::GameState;
+use crate::hh::SearchHistory;
 use crate::moves::PrevMoves;
 use crate::perft::HashPerft;
-use crate::searcher::{iterative_deepening, lazy_smp};
-use crate::uci::Uci;
+use crate::searcher::{iterative_deepening, lazy_smp, SearchOptions};
+use crate::uci::{read_line, Uci};
+use crate::xboard::XBoard;
 use server::run_http;
 use std::env::args;
 use std::time::Instant;
+use time_manager::{SearchControl, SearchLimits};
 use tt::{NoTTable, SmpTTable, TTable};
 
 use board::Zorb;
@@ -27,8 +30,11 @@ pub mod moves;
 pub mod perft;
 pub mod searcher;
 pub mod server;
+pub mod skill;
+pub mod time_manager;
 pub mod tt;
 pub mod uci;
+pub mod xboard;
 
 pub fn init() {
     Zorb::init();
@@ -64,7 +70,13 @@ async fn main() {
 
     // let mut uci = Uci::new(GameState::new_smp(8));
 
-    GameState::new().start();
+    // the protocol is picked from the first command the gui sends
+    match read_line() {
+        Ok(Some(line)) if line.trim() == "xboard" => XBoard::new(GameState::new()).start(),
+        Ok(Some(line)) => GameState::new().start(Some(line)),
+        Ok(None) => {}
+        Err(_) => GameState::new().start(None),
+    }
 }
 
 fn _do_perft() {
@@ -165,19 +177,39 @@ fn _do_search() {
 
     let b = Board::new();
     let prev_moves = PrevMoves::new();
+    let limits = SearchLimits::new_movetime(5000);
+    let options = SearchOptions::default();
+    let control = SearchControl::new();
+    let history = &mut SearchHistory::new();
 
     let start = Instant::now();
 
     let res = match num_threads {
-        0 => iterative_deepening(&b, NoTTable::default(), prev_moves).unwrap(),
-        1 => iterative_deepening(&b, &mut TTable::new(), prev_moves).unwrap(),
-        t => lazy_smp(&b, SmpTTable::new(), prev_moves, t).unwrap(),
+        0 => {
+            let tt = NoTTable::default();
+            iterative_deepening(&b, tt, prev_moves, history, &limits, &options, control).unwrap()
+        }
+        1 => {
+            let tt = &mut TTable::new();
+            iterative_deepening(&b, tt, prev_moves, history, &limits, &options, control).unwrap()
+        }
+        t => lazy_smp(
+            &b,
+            SmpTTable::new(),
+            prev_moves,
+            history,
+            &limits,
+            &options,
+            control,
+            t,
+        )
+        .unwrap(),
     };
 
     println!(
         "bestmove: {} with score {}, took {}ms",
-        res.1.as_uci_string(),
-        res.0,
+        res[0].best_move().as_uci_string(),
+        res[0].score,
         start.elapsed().as_millis()
     );
 }
@@ -1,7 +1,10 @@
 use crate::board::{Board, BLACK, WHITE};
 use crate::eval::PIECE_VALUES;
 use crate::move_info::SQUARES;
-use crate::movegen::{get_all_attackers, NO_SQUARES};
+use crate::movegen::{
+    gen_attack, gen_check_moves, gen_king_castle, gen_quiet, get_all_attackers, is_pseudo_legal,
+    ALL_SQUARES, NO_SQUARES,
+};
 use crate::moves::Move;
 use crate::searcher::Searcher;
 use crate::tt::TT;
@@ -9,10 +12,11 @@ use std::cmp::max;
 
 pub const MAX_MOVES: usize = 214;
 const BEST_MOVE_SCORE: i32 = i32::MAX;
-const KILLER_OFFSET: i32 = 10000;
+// above any history score so killers come before the other quiets
+const KILLER_OFFSET: i32 = 100_000;
 const CAP_SCORE_MUL: i32 = 10000;
 
-pub trait MoveList: IntoIterator<Item = Move> {
+pub trait MoveList {
     fn add_move(&mut self, m: Move);
     fn len(&self) -> usize;
 
@@ -73,74 +77,189 @@ impl<const N: usize> MoveList for StackMoveList<N> {
     }
 }
 
-pub struct ScoredMoveList<'a, T: TT, const N: usize> {
-    moves: [(Move, i32); N],
+// moves are handed out by stage, each stage is only generated once the previous one runs out so a
+// cutoff by an early move saves generating (and scoring) the rest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    HashMoves,
+    GenCaptures,
+    GoodCaptures,
+    Killers,
+    GenQuiets,
+    Quiets,
+    BadCaptures,
+    GenEvasions,
+    Evasions,
+    Done,
+}
+
+// moves are generated in stages: pv and tt moves, good captures, killers and the countermove,
+// quiets by history and finally losing captures
+pub struct ScoredMoveList {
+    moves: [(Move, i32); MAX_MOVES],
     length: usize,
-    board: &'a Board,
-    searcher: &'a Searcher<T>,
+    next: usize,
+    // captures that lose material wait at the front of the list until the quiets are searched
+    bad_captures: (usize, usize),
+    stage: Stage,
     depth: usize,
     pv: Move,
     tt_bm: Option<Move>,
+    // the pv, tt, killer and counter moves, these are handed out before their stage is generated
+    // and are skipped when it is
+    early_moves: [Move; 5],
+    early_index: usize,
 }
 
-impl<'a, T: TT> ScoredMoveList<'a, T, MAX_MOVES> {
-    pub fn new(
-        board: &'a Board,
-        searcher: &'a Searcher<T>,
+impl ScoredMoveList {
+    pub fn new<T: TT>(
+        board: &Board,
+        searcher: &Searcher<T>,
         depth: usize,
-    ) -> ScoredMoveList<'a, T, MAX_MOVES> {
+        in_check: bool,
+    ) -> ScoredMoveList {
+        let pv = searcher.pv_table.get(searcher.ply as usize);
+        let tt_bm = searcher.tt.get_bestmove(board.hash());
+
+        // a hash move from another position might not get out of check, so all evasions are
+        // generated together and the hash and killer moves are only ordered first
+        let (stage, early_moves) = if in_check {
+            (Stage::GenEvasions, [Move::empty(); 5])
+        } else {
+            let mut early_moves = [Move::empty(); 5];
+            let killers = searcher.history.km.get_kms(depth);
+            let counter = searcher
+                .prev_move(1)
+                .and_then(|prev| searcher.history.cm.get(prev));
+            let candidates = [Some(pv), tt_bm, killers[0], killers[1], counter];
+
+            for (i, m) in candidates.into_iter().enumerate() {
+                let m = m.unwrap_or(Move::empty());
+                if !early_moves[..i].contains(&m) {
+                    early_moves[i] = m;
+                }
+            }
+
+            (Stage::HashMoves, early_moves)
+        };
+
         ScoredMoveList {
             moves: [(Move::empty(), 0); MAX_MOVES],
             length: 0,
-            board,
-            searcher,
+            next: 0,
+            bad_captures: (0, 0),
+            stage,
             depth,
-            pv: searcher.pv_table.get(searcher.ply as usize),
-            tt_bm: searcher.tt.get_bestmove(board.hash()),
+            pv,
+            tt_bm,
+            early_moves,
+            early_index: 0,
         }
     }
-}
 
-impl<'a, T: TT, const N: usize> ScoredMoveList<'a, T, N> {
-    pub fn with_size(
-        board: &'a Board,
-        searcher: &'a Searcher<T>,
-        depth: usize,
-    ) -> ScoredMoveList<'a, T, N> {
-        ScoredMoveList {
-            moves: [(Move::empty(), 0); N],
-            length: 0,
-            board,
-            searcher,
-            depth,
-            pv: searcher.pv_table.get(searcher.ply as usize),
-            tt_bm: searcher.tt.get_bestmove(board.hash()),
+    pub fn next_move<T: TT>(&mut self, b: &Board, s: &Searcher<T>) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::HashMoves => match self.next_early_move(b, 2) {
+                    Some(m) => return Some(m),
+                    None => self.stage = Stage::GenCaptures,
+                },
+                Stage::GenCaptures => {
+                    gen_attack(b, self, NO_SQUARES, ALL_SQUARES);
+                    self.score_moves(|m| see(b, m));
+                    self.stage = Stage::GoodCaptures;
+                }
+                Stage::GoodCaptures => match self.pick_best(self.length, 0) {
+                    Some(m) => return Some(m),
+                    None => {
+                        self.bad_captures = (self.next, self.length);
+                        self.next = self.length;
+                        self.stage = Stage::Killers;
+                    }
+                },
+                Stage::Killers => match self.next_early_move(b, 5) {
+                    Some(m) => return Some(m),
+                    None => self.stage = Stage::GenQuiets,
+                },
+                Stage::GenQuiets => {
+                    gen_quiet(b, self, NO_SQUARES, ALL_SQUARES);
+                    gen_king_castle(b, self);
+                    let depth = self.depth;
+                    self.score_moves(|m| score_quiet(b, s, depth, m));
+                    self.stage = Stage::Quiets;
+                }
+                Stage::Quiets => match self.pick_best(self.length, i32::MIN) {
+                    Some(m) => return Some(m),
+                    None => {
+                        self.next = self.bad_captures.0;
+                        self.stage = Stage::BadCaptures;
+                    }
+                },
+                Stage::BadCaptures => match self.pick_best(self.bad_captures.1, i32::MIN) {
+                    Some(m) => return Some(m),
+                    None => self.stage = Stage::Done,
+                },
+                Stage::GenEvasions => {
+                    gen_check_moves(b, self);
+                    let (depth, pv, tt_bm) = (self.depth, self.pv, self.tt_bm);
+                    self.score_moves(|m| score_move(b, s, depth, m, pv, tt_bm));
+                    self.stage = Stage::Evasions;
+                }
+                Stage::Evasions => match self.pick_best(self.length, i32::MIN) {
+                    Some(m) => return Some(m),
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => return None,
+            }
         }
     }
-}
 
-impl<'a, T: TT, const N: usize> IntoIterator for ScoredMoveList<'a, T, N> {
-    type Item = Move;
-    type IntoIter = ScoreMoveListIter<N>;
+    // the early moves up to end that can be played here, they can all come from another position
+    // after an index collision or a different line
+    fn next_early_move(&mut self, b: &Board, end: usize) -> Option<Move> {
+        while self.early_index < end {
+            let m = self.early_moves[self.early_index];
+            self.early_index += 1;
 
-    fn into_iter(self) -> Self::IntoIter {
-        // self.moves[0..self.length].sort_unstable_by(|m1, m2| m2.1.cmp(&m1.1));
-        ScoreMoveListIter::new(self.moves, self.length)
+            if m != Move::empty() && is_pseudo_legal(b, m) {
+                return Some(m);
+            }
+
+            // a move that can't be played must not stop a generated move that looks the same
+            self.early_moves[self.early_index - 1] = Move::empty();
+        }
+
+        None
+    }
+
+    // scores the moves added since the last stage
+    fn score_moves(&mut self, score: impl Fn(Move) -> i32) {
+        for (m, s) in self.moves[self.next..self.length].iter_mut() {
+            *s = score(*m);
+        }
+    }
+
+    // swaps the best move left before end to the front of what is left and hands it out, unless
+    // it scores below min_score
+    fn pick_best(&mut self, end: usize, min_score: i32) -> Option<Move> {
+        let best = (self.next..end)
+            .max_by_key(|i| self.moves[*i].1)
+            .filter(|i| self.moves[*i].1 >= min_score)?;
+
+        self.moves.swap(self.next, best);
+        self.next += 1;
+
+        Some(self.moves[self.next - 1].0)
     }
 }
 
-impl<'a, T: TT, const N: usize> MoveList for ScoredMoveList<'a, T, N> {
+impl MoveList for ScoredMoveList {
     fn add_move(&mut self, m: Move) {
-        let score = score_move(
-            self.board,
-            self.searcher,
-            self.depth,
-            m,
-            self.pv,
-            self.tt_bm,
-        );
+        if self.early_moves.contains(&m) {
+            return;
+        }
 
-        self.moves[self.length] = (m, score);
+        self.moves[self.length] = (m, 0);
         self.length += 1;
     }
 
@@ -149,7 +268,15 @@ impl<'a, T: TT, const N: usize> MoveList for ScoredMoveList<'a, T, N> {
     }
 }
 
-pub struct QSearchMoveList<'a, T: TT, const N: usize>(ScoredMoveList<'a, T, N>);
+pub struct QSearchMoveList<'a, T: TT, const N: usize> {
+    moves: [(Move, i32); N],
+    length: usize,
+    board: &'a Board,
+    searcher: &'a Searcher<T>,
+    depth: usize,
+    pv: Move,
+    tt_bm: Option<Move>,
+}
 
 impl<'a, T: TT, const N: usize> QSearchMoveList<'a, T, N> {
     pub fn new(
@@ -157,9 +284,15 @@ impl<'a, T: TT, const N: usize> QSearchMoveList<'a, T, N> {
         searcher: &'a Searcher<T>,
         depth: usize,
     ) -> QSearchMoveList<'a, T, N> {
-        QSearchMoveList(ScoredMoveList::<'a, T, N>::with_size(
-            board, searcher, depth,
-        ))
+        QSearchMoveList {
+            moves: [(Move::empty(), 0); N],
+            length: 0,
+            board,
+            searcher,
+            depth,
+            pv: searcher.pv_table.get(searcher.ply as usize),
+            tt_bm: searcher.tt.get_bestmove(board.hash()),
+        }
     }
 }
 
@@ -169,33 +302,32 @@ impl<'a, T: TT, const N: usize> IntoIterator for QSearchMoveList<'a, T, N> {
     type IntoIter = ScoreMoveListIter<N>;
 
     fn into_iter(self) -> Self::IntoIter {
-        // self.0.moves[0..self.0.length].sort_unstable_by(|m1, m2| m2.1.cmp(&m1.1));
-        ScoreMoveListIter::new(self.0.moves, self.0.length)
+        ScoreMoveListIter::new(self.moves, self.length)
     }
 }
 
 impl<'a, T: TT, const N: usize> MoveList for QSearchMoveList<'a, T, N> {
     fn add_move(&mut self, m: Move) {
+        // captures that lose material are not worth searching in qsearch
+        if m.move_type().is_cap() && !see_ge(self.board, m, 0) {
+            return;
+        }
+
         let score = score_move(
-            self.0.board,
-            self.0.searcher,
-            self.0.depth,
+            self.board,
+            self.searcher,
+            self.depth,
             m,
-            self.0.pv,
-            self.0.tt_bm,
+            self.pv,
+            self.tt_bm,
         );
 
-        // only add the move if it's SEE is > 0 (accounting for the offset)
-        if m.move_type().is_cap() && score < 0 {
-            return;
-        }
-
-        self.0.moves[self.0.length] = (m, score);
-        self.0.length += 1;
+        self.moves[self.length] = (m, score);
+        self.length += 1;
     }
 
     fn len(&self) -> usize {
-        self.0.length
+        self.length
     }
 }
 
@@ -278,11 +410,16 @@ pub fn score_move<T: TT>(
 }
 
 fn score_quiet<T: TT>(b: &Board, s: &Searcher<T>, depth: usize, m: Move) -> i32 {
-    if let Some(km_priority) = s.km.get_move_priority(m, depth) {
+    if let Some(km_priority) = s.history.km.get_move_priority(m, depth) {
         return KILLER_OFFSET + km_priority;
     }
 
-    s.hh.get(b.ctm(), m.from() as usize, m.to() as usize) as i32
+    s.quiet_history(b, m)
+}
+
+// whether the exchange on the destination square wins at least threshold
+pub fn see_ge(b: &Board, m: Move, threshold: i32) -> bool {
+    see(b, m) >= threshold
 }
 
 fn see(b: &Board, m: Move) -> i32 {
@@ -300,7 +437,12 @@ fn see(b: &Board, m: Move) -> i32 {
     // as there is no sliding piece that can be behind a knight that could attack the target
     let can_xray = occ ^ b.knights(WHITE) ^ b.knights(BLACK);
 
-    gain[depth] = PIECE_VALUES[xpiece];
+    // a quiet move captures nothing, it can only lose the moved piece
+    gain[depth] = if m.move_type().is_cap() {
+        PIECE_VALUES[xpiece]
+    } else {
+        0
+    };
     while from_piece > 0 {
         depth += 1;
 
@@ -310,8 +452,8 @@ fn see(b: &Board, m: Move) -> i32 {
             break;
         }
 
-        // remove this attacker
-        attackers ^= from_piece;
+        // remove this attacker, the first piece might not attack the square if it is a pawn push
+        attackers &= !from_piece;
         occ ^= from_piece;
 
         // recheck if there are any sliding pieces behind this attacker
@@ -367,3 +509,25 @@ fn test_see_scores() {
         assert_eq!(score, see(&b, m))
     }
 }
+
+#[test]
+fn see_ge_handles_quiet_moves_and_thresholds() {
+    crate::init();
+
+    let b = Board::new_fen("4k3/8/2p5/8/3N4/8/8/4K3 w - - 0 1").unwrap();
+
+    // the knight is lost for nothing on a square the pawn defends
+    let hanging = Move::new_from_text("d4b5", &b).unwrap();
+    assert!(!see_ge(&b, hanging, 0));
+    assert!(see_ge(&b, hanging, -PIECE_VALUES[2]));
+
+    let safe = Move::new_from_text("d4f5", &b).unwrap();
+    assert!(see_ge(&b, safe, 0));
+    assert!(!see_ge(&b, safe, 1));
+
+    let b = Board::new_fen("4k3/8/8/3p4/8/4P3/8/4K3 w - - 0 1").unwrap();
+
+    // a pawn push that can be taken but is defended by nothing
+    let push = Move::new_from_text("e3e4", &b).unwrap();
+    assert!(!see_ge(&b, push, 0));
+}
@@ -1,7 +1,11 @@
-use crate::board::{Board, BISHOP, BLACK, KING, KNIGHT, QUEEN, ROOK, WHITE};
-use crate::move_info::{FA, FH, MT, R2, R7, RIGHT_DIR, SQUARES, UP_DIR, UP_LEFT_DIR, UP_RIGHT_DIR};
-use crate::move_list::MoveList;
-use crate::moves::{Move, MoveType, PrevMoves};
+use crate::board::{
+    Board, BISHOP, BLACK, CASTLE_KING_TO, CASTLE_ROOK_TO, KING, KNIGHT, PAWN, QUEEN, ROOK, WHITE,
+};
+use crate::move_info::{
+    FA, FH, MT, R1, R2, R4, R5, R7, R8, RIGHT_DIR, SQUARES, UP_DIR, UP_LEFT_DIR, UP_RIGHT_DIR,
+};
+use crate::move_list::{MoveList, StackMoveList, MAX_MOVES};
+use crate::moves::{Move, MoveType, PrevMoves, NULL_MOVE};
 use std::cmp::{max, min};
 
 pub const ALL_SQUARES: u64 = u64::MAX;
@@ -381,24 +385,66 @@ pub fn gen_king_attack(b: &Board, ml: &mut impl MoveList, pinned: u64, target: u
 }
 
 pub fn gen_king_castle(b: &Board, ml: &mut impl MoveList) {
+    add_castle(b, ml, MoveType::kingside(b.ctm()));
+    add_castle(b, ml, MoveType::queenside(b.ctm()));
+}
+
+// castles are added as the king taking its own rook
+fn add_castle(b: &Board, ml: &mut impl MoveList, move_type: MoveType) {
+    let Some(idx) = move_type.castle_idx() else {
+        return;
+    };
+
+    if !has_castle_right(b, move_type) || !castle_path_is_clear(b, idx) {
+        return;
+    }
+
+    let piece = (KING + b.ctm()) as u32;
+    ml.add_move(Move::new(
+        b.king_idx(b.ctm()) as u32,
+        b.castle_rook(idx) as u32,
+        piece,
+        0,
+        move_type,
+    ));
+}
+
+fn has_castle_right(b: &Board, move_type: MoveType) -> bool {
     let colour_rights = b.castle_state() >> (2 * (b.opp_ctm()));
-    let can_kingside = colour_rights & 0b10 > 0;
-    let can_queenside = colour_rights & 1 > 0;
-    let piece = KING + b.ctm();
 
-    let from = b.king_idx(b.ctm()) as u32;
+    if move_type == MoveType::kingside(b.ctm()) {
+        colour_rights & 0b10 > 0
+    } else if move_type == MoveType::queenside(b.ctm()) {
+        colour_rights & 1 > 0
+    } else {
+        false
+    }
+}
+
+// this covers chess960 where the king and rook can start anywhere on the back rank, so every
+// square either of them passes over has to be empty (apart from the king and rook themselves) and
+// the squares the king passes over can't be attacked
+fn castle_path_is_clear(b: &Board, idx: usize) -> bool {
+    let from = b.king_idx(b.ctm());
+    let rook_from = b.castle_rook(idx);
+    let king_path = get_ray_inclusive(from, CASTLE_KING_TO[idx]);
+    let rook_path = get_ray_inclusive(rook_from, CASTLE_ROOK_TO[idx]);
 
-    let kingside_mask = 0x60 << (b.ctm() * 56);
-    if can_kingside && b.all_occ() & kingside_mask == 0 {
-        let move_type = MoveType::kingside(b.ctm());
-        ml.add_move(Move::new(from, from + 2, piece as u32, 0, move_type));
+    let blockers = b.all_occ() & !(SQUARES[from] | SQUARES[rook_from]);
+    if (king_path | rook_path) & blockers > 0 {
+        return false;
     }
 
-    let queenside_mask = 0xE << (b.ctm() * 56);
-    if can_queenside && b.all_occ() & queenside_mask == 0 {
-        let move_type = MoveType::queenside(b.ctm());
-        ml.add_move(Move::new(from, from - 2, piece as u32, 0, move_type));
+    // the king is not in check when castles are generated, so its own square can be skipped
+    let mut passed = king_path & !SQUARES[from];
+    while passed > 0 {
+        if sq_attacked(b, passed.trailing_zeros() as usize, b.opp_ctm()) {
+            return false;
+        }
+        passed &= passed - 1;
     }
+
+    true
 }
 
 pub fn king_safe_quiet_moves(b: &Board) -> u64 {
@@ -520,15 +566,19 @@ pub fn get_ray_inbetween(sq1: usize, sq2: usize) -> u64 {
 
     let dir = match diff {
         _ if higher / 8 == lower / 8 => RIGHT_DIR,
-        _ if diff % 8 == 0 => UP_DIR,
-        _ if diff % 7 == 0 => UP_LEFT_DIR,
-        _ if diff % 9 == 0 => UP_RIGHT_DIR,
+        _ if diff.is_multiple_of(8) => UP_DIR,
+        _ if diff.is_multiple_of(7) => UP_LEFT_DIR,
+        _ if diff.is_multiple_of(9) => UP_RIGHT_DIR,
         _ => return 0,
     };
 
     MT::rays(dir, lower) & (SQUARES[higher] - 1)
 }
 
+pub fn get_ray_inclusive(sq1: usize, sq2: usize) -> u64 {
+    get_ray_inbetween(sq1, sq2) | SQUARES[sq1] | SQUARES[sq2]
+}
+
 #[inline]
 pub fn get_piece(board: &Board, sq: u32) -> Option<u32> {
     find_piece(board, sq, board.ctm())
@@ -601,12 +651,150 @@ pub fn is_legal_move(board: &Board, m: Move, prev_moves: &PrevMoves) -> bool {
         return false;
     }
 
-    match m.move_type() {
-        // check castle moves to see if the king passes through an attacked square
-        MoveType::WKingSide => !sq_attacked(board, 5, 1) & !sq_attacked(board, 6, 1),
-        MoveType::WQueenSide => !sq_attacked(board, 3, 1) & !sq_attacked(board, 2, 1),
-        MoveType::BKingSide => !sq_attacked(board, 61, 0) & !sq_attacked(board, 62, 0),
-        MoveType::BQueenSide => !sq_attacked(board, 59, 0) & !sq_attacked(board, 58, 0),
-        _ => true,
+    castle_is_legal(board, m)
+}
+
+// board is the position after the move has been made. The squares the king passes over are checked
+// when castles are generated, but in chess960 moving the rook can still uncover a check on the king
+// (and the king might not have moved at all, which moved_into_check does not look at)
+fn castle_is_legal(board: &Board, m: Move) -> bool {
+    match m.move_type().castle_idx() {
+        Some(idx) => !sq_attacked(board, CASTLE_KING_TO[idx], board.ctm()),
+        None => true,
+    }
+}
+
+// whether a move that did not come from the move generator, like a tt or killer move that might be
+// from another position, is one the generator could have made here. This is much cheaper than
+// generating the moves, but like the generator it ignores pins and checks on the king
+pub fn is_pseudo_legal(b: &Board, m: Move) -> bool {
+    let (from, to, piece, xpiece, move_type) = m.all();
+
+    if m == NULL_MOVE || get_piece(b, from as u32) != Some(piece as u32) {
+        return false;
+    }
+
+    if let Some(idx) = move_type.castle_idx() {
+        return piece == KING + b.ctm()
+            && to == b.castle_rook(idx)
+            && has_castle_right(b, move_type)
+            && castle_path_is_clear(b, idx);
+    }
+
+    let target = SQUARES[to];
+    let is_pawn = piece == PAWN + b.ctm();
+    let last_rank = if b.ctm() == WHITE { R8 } else { R1 };
+
+    // pawns promote on the last rank and only there, and only pawns promote, push or take ep
+    if is_pawn && (target & last_rank > 0) != move_type.is_promo() {
+        return false;
+    }
+
+    if !is_pawn && (move_type.is_promo() || matches!(move_type, MoveType::Double | MoveType::Ep)) {
+        return false;
+    }
+
+    let attacks = piece_attacks(b, piece, from);
+    let empty = !b.all_occ();
+    let (push, double) = if b.ctm() == WHITE {
+        let push = (SQUARES[from] << 8) & empty;
+        (push, (push << 8) & empty & R4)
+    } else {
+        let push = (SQUARES[from] >> 8) & empty;
+        (push, (push >> 8) & empty & R5)
+    };
+
+    match move_type {
+        MoveType::Quiet if is_pawn => xpiece == 0 && push & target > 0,
+        MoveType::Quiet => xpiece == 0 && attacks & empty & target > 0,
+        MoveType::Promo => {
+            PROMO_PIECES.iter().any(|p| *p as usize + b.ctm() == xpiece) && push & target > 0
+        }
+        MoveType::Double => xpiece == 0 && double & target > 0,
+        MoveType::Ep => to == b.ep() && xpiece == PAWN + b.opp_ctm() && attacks & target > 0,
+        _ => get_xpiece(b, to as u32) == Some(xpiece as u32) && attacks & target > 0,
+    }
+}
+
+// the squares a piece attacks, for a pawn these are its captures
+fn piece_attacks(b: &Board, piece: usize, from: usize) -> u64 {
+    match piece - b.ctm() {
+        PAWN => MT::pawn_attacks(b.ctm(), from),
+        KNIGHT => MT::knight_moves(from),
+        ROOK => MT::rook_moves(b.all_occ(), from),
+        BISHOP => MT::bishop_moves(b.all_occ(), from),
+        QUEEN => MT::rook_moves(b.all_occ(), from) | MT::bishop_moves(b.all_occ(), from),
+        _ => MT::king_moves(from),
+    }
+}
+
+// checks a move that did not come from the move generator (ignoring repetitions), this generates
+// every move so it should not be used in the search itself
+pub fn is_legal(board: &Board, m: Move) -> bool {
+    let mut ml = StackMoveList::<MAX_MOVES>::new();
+    gen_moves(board, &mut ml, is_in_check(board));
+
+    if !ml.contains_move(m) {
+        return false;
+    }
+
+    let b = board.copy_make(m);
+    !moved_into_check(&b, m) && castle_is_legal(&b, m)
+}
+
+// whether the side to move has any legal move, used to tell a finished game from an aborted search
+pub fn has_legal_move(board: &Board) -> bool {
+    let mut ml = StackMoveList::<MAX_MOVES>::new();
+    gen_moves(board, &mut ml, is_in_check(board));
+
+    ml.any(|m| {
+        let b = board.copy_make(m);
+        !moved_into_check(&b, m) && castle_is_legal(&b, m)
+    })
+}
+
+#[test]
+fn pseudo_legal_moves_match_the_generator() {
+    crate::init();
+
+    let fens = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    ];
+
+    let generated = |b: &Board| -> Vec<Move> {
+        let mut ml = StackMoveList::<MAX_MOVES>::new();
+        gen_all_moves(b, &mut ml);
+        ml.collect()
+    };
+
+    // every position one move from the start positions, skipping checks as only evasions are
+    // generated there
+    let positions: Vec<Board> = fens
+        .iter()
+        .map(|fen| Board::new_fen(fen).unwrap())
+        .flat_map(|b| generated(&b).into_iter().map(move |m| b.copy_make(m)))
+        .filter(|b| !is_in_check(b))
+        .collect();
+
+    let all_moves: Vec<Move> = positions.iter().flat_map(generated).collect();
+
+    for b in &positions {
+        let moves = generated(b);
+
+        for m in &all_moves {
+            assert_eq!(
+                is_pseudo_legal(b, *m),
+                moves.contains(m),
+                "{} {}",
+                b.to_fen(),
+                m.as_uci_string()
+            );
+        }
     }
+
+    assert!(!is_pseudo_legal(&positions[0], NULL_MOVE));
 }
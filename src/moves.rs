@@ -10,9 +10,12 @@ movetype 0-12,  4 bits
 ep, last castle state and last halfmove can all be stored in searchers - aha not with copy move tho
 */
 
-use crate::board::{Board, PIECE_NAMES, WHITE};
+use crate::board::{
+    Board, BKS_STATE, BQS_STATE, CASTLE_KING_TO, PIECE_NAMES, WKS_STATE, WQS_STATE,
+};
+use crate::error::InvalidUciCommand;
 use crate::move_info::SQ_NAMES;
-use crate::movegen::{get_piece, get_xpiece};
+use crate::movegen::{get_piece, get_xpiece, is_legal};
 use crate::searcher::MAX_DEPTH;
 use std::fmt::{Display, Formatter};
 
@@ -58,6 +61,18 @@ impl MoveType {
         }
     }
 
+    // the castle right used by a castle move, in the same order as the board castle state
+    #[inline]
+    pub fn castle_idx(&self) -> Option<usize> {
+        match self {
+            MoveType::WKingSide => Some(WKS_STATE),
+            MoveType::WQueenSide => Some(WQS_STATE),
+            MoveType::BKingSide => Some(BKS_STATE),
+            MoveType::BQueenSide => Some(BQS_STATE),
+            _ => None,
+        }
+    }
+
     pub fn is_promo(&self) -> bool {
         matches!(
             self,
@@ -181,69 +196,78 @@ impl Move {
         )
     }
 
-    pub fn new_from_text(text: &str, b: &Board) -> Move {
-        let from = sq_from_text(&text[0..2]) as u32;
-        let to = sq_from_text(&text[2..4]) as u32;
+    // parses a move in uci notation, only accepting it if it is legal in the position
+    pub fn new_from_text(text: &str, b: &Board) -> Result<Move, InvalidUciCommand> {
+        let invalid = || InvalidUciCommand::InvalidMove(text.into());
 
-        let promo = if text.len() == 5 {
-            Some(promo_piece_from_text(&text[4..]) + b.ctm())
-        } else {
-            None
-        };
+        if !text.is_ascii() || !(4..=5).contains(&text.len()) {
+            return Err(invalid());
+        }
+
+        let from = sq_from_text(&text[0..2]).ok_or_else(invalid)? as u32;
+        let mut to = sq_from_text(&text[2..4]).ok_or_else(invalid)? as u32;
 
-        let promo_piece = (promo.unwrap_or(12)) as u32;
+        let promo_piece = match &text[4..] {
+            "" => 12,
+            promo => (promo_piece_from_text(promo).ok_or_else(invalid)? + b.ctm()) as u32,
+        };
 
-        let piece = get_piece(b, from).unwrap();
+        let piece =
+            get_piece(b, from).ok_or_else(|| InvalidUciCommand::IllegalMove(text.into()))?;
 
         let mut move_type = MoveType::Quiet;
 
         if piece < 2 && from.abs_diff(to) == 16 {
             move_type = MoveType::Double;
-        } else if (piece == 10 || piece == 11) && from.abs_diff(to) == 2 {
-            match from.cmp(&to) {
-                std::cmp::Ordering::Less => {
-                    move_type = if b.ctm() == WHITE {
-                        MoveType::WKingSide
-                    } else {
-                        MoveType::BKingSide
-                    };
-                }
-                std::cmp::Ordering::Greater => {
-                    move_type = if b.ctm() == WHITE {
-                        MoveType::WQueenSide
-                    } else {
-                        MoveType::BQueenSide
-                    };
-                }
-                _ => {}
+        } else if piece == 10 || piece == 11 {
+            if let Some((castle, rook)) = castle_from_text(b, from, to) {
+                move_type = castle;
+                to = rook;
             }
         }
 
-        let mut xpiece = get_xpiece(b, to).unwrap_or(12);
-        if xpiece < 12 && promo_piece < 12 {
+        // match the xpiece of generated moves so that text moves can be compared against them
+        let captured = get_xpiece(b, to);
+        let mut xpiece = captured.unwrap_or(0);
+        if captured.is_some() && promo_piece < 12 {
             match promo_piece {
                 2 | 3 => move_type = MoveType::NPromoCap,
                 4 | 5 => move_type = MoveType::RPromoCap,
                 6 | 7 => move_type = MoveType::BPromoCap,
-                8 | 9 => move_type = MoveType::QPromoCap,
-                _ => panic!("promo_piece {promo_piece} not an available promo piece"),
+                _ => move_type = MoveType::QPromoCap,
             }
         } else if promo_piece < 12 {
             move_type = MoveType::Promo;
             xpiece = promo_piece;
         } else if piece < 2 && to == b.ep() as u32 {
             move_type = MoveType::Ep;
-        } else if xpiece < 12 {
+            xpiece = b.opp_ctm() as u32;
+        } else if captured.is_some() {
             move_type = MoveType::Cap;
         }
 
-        Move::new(from, to, piece, xpiece, move_type)
+        let m = Move::new(from, to, piece, xpiece, move_type);
+
+        if !is_legal(b, m) {
+            return Err(InvalidUciCommand::IllegalMove(text.into()));
+        }
+
+        Ok(m)
     }
 
     pub fn as_uci_string(&self) -> String {
+        self.as_uci_notation(false)
+    }
+
+    // castles are stored as the king taking its own rook, which is only the notation in chess960
+    pub fn as_uci_notation(&self, chess960: bool) -> String {
         let mut mv = String::new();
 
-        let (f, t, _, x, m) = self.all();
+        let (f, mut t, _, x, m) = self.all();
+
+        if let Some(idx) = m.castle_idx().filter(|_| !chess960) {
+            t = CASTLE_KING_TO[idx];
+        }
 
         mv.push_str(SQ_NAMES[f]);
         mv.push_str(SQ_NAMES[t]);
@@ -279,19 +303,37 @@ impl From<Move> for u32 {
     }
 }
 
-fn sq_from_text(sq: &str) -> usize {
-    let sq = sq.as_bytes();
+// castles are either written as the king moving two squares or, in chess960, as the king taking
+// its own rook. Returns the castle type and the square of the castling rook
+fn castle_from_text(b: &Board, from: u32, to: u32) -> Option<(MoveType, u32)> {
+    [MoveType::kingside(b.ctm()), MoveType::queenside(b.ctm())]
+        .into_iter()
+        .find_map(|move_type| {
+            let idx = move_type.castle_idx()?;
+            let rook = b.castle_rook(idx) as u32;
+            let has_right = b.castle_state() & (0b1000 >> idx) > 0;
+            let is_castle =
+                to == rook || (from.abs_diff(to) == 2 && to == CASTLE_KING_TO[idx] as u32);
+
+            (has_right && is_castle).then_some((move_type, rook))
+        })
+}
 
-    ((sq[0] - "a".as_bytes()[0]) + (8 * (sq[1] - "1".as_bytes()[0]))) as usize
+fn sq_from_text(sq: &str) -> Option<usize> {
+    let mut chars = sq.chars();
+    let file = chars.next().filter(|c| ('a'..='h').contains(c))?;
+    let rank = chars.next().filter(|c| ('1'..='8').contains(c))?;
+
+    Some((file as usize - 'a' as usize) + 8 * (rank as usize - '1' as usize))
 }
 
-fn promo_piece_from_text(p: &str) -> usize {
+fn promo_piece_from_text(p: &str) -> Option<usize> {
     match p {
-        "n" => 2,
-        "r" => 4,
-        "b" => 6,
-        "q" => 8,
-        _ => 12,
+        "n" => Some(2),
+        "r" => Some(4),
+        "b" => Some(6),
+        "q" => Some(8),
+        _ => None,
     }
 }
 
@@ -384,3 +426,48 @@ impl Default for KillerMoves {
         Self::new()
     }
 }
+
+#[test]
+fn castle_notation() {
+    crate::init();
+
+    let b = Board::new_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+    let castle = Move::new_from_text("e1g1", &b).unwrap();
+    assert_eq!(castle.move_type(), MoveType::WKingSide);
+    assert_eq!(castle, Move::new_from_text("e1h1", &b).unwrap());
+    assert_eq!(castle.as_uci_string(), "e1g1");
+    assert_eq!(castle.as_uci_notation(true), "e1h1");
+
+    // the king already stands on its castled square, so castling only moves the rook
+    let b = Board::new_fen("1r4kr/8/8/8/8/8/8/1R4KR w BHbh - 0 1").unwrap();
+    let castle = Move::new_from_text("g1b1", &b).unwrap();
+    assert_eq!(castle.move_type(), MoveType::WQueenSide);
+    assert_eq!(castle.as_uci_string(), "g1c1");
+
+    let after = b.copy_make(castle);
+    assert_eq!(after.king_idx(crate::board::WHITE), 2);
+    assert_eq!(after.rooks(crate::board::WHITE), (1 << 3) | (1 << 7));
+    assert_eq!(after.castle_state(), 0b0011);
+    assert_eq!(after.hash(), crate::board::gen_hash(after));
+}
+
+#[test]
+fn bad_text_moves_are_rejected() {
+    crate::init();
+
+    let b = Board::new();
+    assert!(Move::new_from_text("e2e4", &b).is_ok());
+
+    for text in [
+        "", "e2", "e2e", "e2e4qq", "z2e4", "e9e4", "e2e4k", "é2e4", "e7e5", "e2e5", "e1g1",
+    ] {
+        assert!(Move::new_from_text(text, &b).is_err(), "{text}");
+    }
+
+    let b = Board::new_fen("8/P6k/8/8/8/8/8/K7 w - - 0 1").unwrap();
+    assert_eq!(
+        Move::new_from_text("a7a8n", &b).unwrap().move_type(),
+        MoveType::Promo
+    );
+    assert!(Move::new_from_text("a7a8", &b).is_err());
+}
@@ -6,6 +6,23 @@ use crate::movegen::*;
 use crate::moves::{Move, PrevMoves};
 use crate::tt::PerftTT;
 
+// the position perftree asks for, reporting bad input rather than panicking
+fn perftree_board(fen: &str, moves_strs: Option<&String>) -> Option<Board> {
+    let board = Board::new_fen(fen)
+        .map_err(|err| eprintln!("Invalid fen {fen}: {err}"))
+        .ok()?;
+
+    moves_strs
+        .map_or("", |moves| moves.as_str())
+        .split_whitespace()
+        .try_fold(board, |board, m| {
+            Move::new_from_text(m, &board)
+                .map(|m| board.copy_make(m))
+                .map_err(|err| eprintln!("{err}"))
+                .ok()
+        })
+}
+
 pub struct Perft {
     pub mc: usize,
     prev_moves: PrevMoves,
@@ -47,12 +64,9 @@ impl Perft {
     }
 
     pub fn perftree_root(&self, depth: usize, fen: &str, moves_strs: Option<&String>) {
-        let mut board = Board::new_fen(fen).unwrap();
-        if let Some(moves_str) = moves_strs {
-            for m in moves_str.split(' ') {
-                board = board.copy_make(Move::new_from_text(m, &board));
-            }
-        }
+        let Some(board) = perftree_board(fen, moves_strs) else {
+            return;
+        };
 
         let mut total = 0;
 
@@ -143,12 +157,9 @@ impl HashPerft {
     }
 
     pub fn perftree_root(&mut self, depth: usize, fen: &str, moves_strs: Option<&String>) {
-        let mut board = Board::new_fen(fen).unwrap();
-        if let Some(moves_str) = moves_strs {
-            for m in moves_str.split(' ') {
-                board = board.copy_make(Move::new_from_text(m, &board));
-            }
-        }
+        let Some(board) = perftree_board(fen, moves_strs) else {
+            return;
+        };
 
         let mut total = 0;
 
@@ -197,3 +208,48 @@ impl HashPerft {
         move_count
     }
 }
+
+#[test]
+fn chess960_perft() {
+    crate::init();
+
+    let perfts = [
+        // standard positions to check that normal castling still works
+        (
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            4,
+            197281,
+        ),
+        (
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -",
+            3,
+            97862,
+        ),
+        (
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            3,
+            9467,
+        ),
+        // chess960 positions with shredder fen castle rights
+        (
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+            3,
+            12189,
+        ),
+        (
+            "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+            3,
+            18002,
+        ),
+        (
+            "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+            3,
+            10471,
+        ),
+    ];
+
+    for (fen, depth, expected) in perfts {
+        let b = Board::new_fen(fen).unwrap();
+        assert_eq!(HashPerft::new().perft(&b, depth), expected, "{fen}");
+    }
+}
@@ -1,27 +1,173 @@
-use std::{sync::Arc, time::Instant};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use crate::{
-    board::{Board, BLACK, KING, WHITE},
+    board::{Board, KING, WHITE},
     error::SearchError,
-    eval::{eval, MATED, PIECE_VALUES},
+    eval::{eval, MATED},
     eval::{CHECKMATE, STALEMATE},
-    hh::HistoryTable,
-    move_list::{QSearchMoveList, ScoredMoveList},
-    movegen::{gen_all_attacks, gen_moves, is_in_check, is_legal_move, moved_into_check},
-    moves::{KillerMoves, Move, MoveType, PrevMoves, NULL_MOVE},
+    hh::{history_bonus, SearchHistory},
+    move_list::{see_ge, MoveList, QSearchMoveList, ScoredMoveList, StackMoveList, MAX_MOVES},
+    movegen::{gen_all_attacks, is_in_check, is_legal, is_legal_move, moved_into_check},
+    moves::{Move, MoveType, PrevMoves, NULL_MOVE},
+    skill::{Skill, MAX_ELO, MAX_SKILL_LEVEL},
+    time_manager::{SearchControl, SearchLimits, TimeManager, DEFAULT_MOVE_OVERHEAD_MS},
     tt::{
         EntryScore::{self},
         SmpTTable, TT,
     },
 };
 
-const TIME_LIMIT_MS: u128 = 5000;
 pub const MAX_DEPTH: usize = 100;
 pub const MIN_SCORE: i32 = CHECKMATE * 2;
 const MAX_SCORE: i32 = -MIN_SCORE;
 const QSEARCH_MAX_PLY: usize = 50;
+// how often the main searcher reports progress while searching
+const INFO_INTERVAL_MS: u128 = 1000;
+// currmove is only reported once a search has been running for a while to avoid flooding the gui
+const CURRMOVE_DELAY_MS: u128 = 3000;
+// from this depth each line is searched in a window of +-ASPIRATION_WINDOW around its last
+// score, the window doubles every time the score falls outside it
+const ASPIRATION_MIN_DEPTH: usize = 4;
+const ASPIRATION_WINDOW: i32 = 25;
+const ASPIRATION_MAX_DELTA: i32 = 1000;
+// a null move is tried from this depth, searched NMP_REDUCTION + depth / NMP_DEPTH_DIV plies
+// shallower than the node
+const NMP_MIN_DEPTH: usize = 3;
+const NMP_REDUCTION: usize = 3;
+const NMP_DEPTH_DIV: usize = 4;
+// from this depth a null move cutoff is only trusted once a reduced search without null moves
+// agrees, which catches most zugzwang positions
+const NMP_VERIFY_DEPTH: usize = 10;
+// the tt move is checked for singularity from this depth if the tt entry is at most
+// SE_TT_DEPTH_MARGIN plies shallower. The other moves have to stay SE_MARGIN * depth below it
+const SE_MIN_DEPTH: usize = 7;
+const SE_TT_DEPTH_MARGIN: usize = 3;
+const SE_MARGIN: i32 = 2;
+// quiet moves are reduced from this depth once this many moves have been searched, by
+// LMR_BASE + ln(depth) * ln(move number) / LMR_DIV plies
+const LMR_MIN_DEPTH: usize = 3;
+const LMR_MIN_MOVES: usize = 3;
+const LMR_MAX_MOVES: usize = 64;
+const LMR_BASE: f64 = 0.75;
+const LMR_DIV: f64 = 2.25;
+// every this much history takes a ply off the reduction and every this much below zero adds one,
+// up to two plies either way
+const LMR_HISTORY_DIV: i32 = 4096;
+// up to this depth a node fails high without searching if the static eval beats beta by
+// RFP_MARGIN per ply
+const RFP_MAX_DEPTH: usize = 6;
+const RFP_MARGIN: i32 = 80;
+// from this depth captures that win enough by SEE are searched PROBCUT_REDUCTION plies shallower
+// against beta + PROBCUT_MARGIN, if one holds the node is cut
+const PROBCUT_MIN_DEPTH: usize = 5;
+const PROBCUT_REDUCTION: usize = 4;
+const PROBCUT_MARGIN: i32 = 200;
+// from these depths a node without a tt move is searched a ply shallower, the shallower search
+// fills in the tt move for the next visit. Non-pv nodes can't be told apart into cut and all
+// nodes, so they wait for more depth
+const IIR_PV_MIN_DEPTH: usize = 4;
+const IIR_MIN_DEPTH: usize = 6;
+// up to this depth quiet moves are skipped once the static eval plus
+// FP_BASE + FP_MARGIN * depth cannot reach alpha
+const FP_MAX_DEPTH: usize = 3;
+const FP_BASE: i32 = 50;
+const FP_MARGIN: i32 = 100;
+// up to this depth a node whose static eval is RAZOR_BASE + RAZOR_MARGIN * depth below alpha
+// drops into qsearch, and fails low if qsearch can't get above alpha either
+const RAZOR_MAX_DEPTH: usize = 3;
+const RAZOR_BASE: i32 = 200;
+const RAZOR_MARGIN: i32 = 150;
+// up to this depth quiet moves are skipped once LMP_BASE + depth * depth moves have been searched
+const LMP_MAX_DEPTH: usize = 4;
+const LMP_BASE: usize = 3;
+// up to this depth moves are skipped if they lose more than a margin per ply in a static exchange
+const SEE_PRUNE_MAX_DEPTH: usize = 6;
+const SEE_QUIET_MARGIN: i32 = 60;
+const SEE_CAPTURE_MARGIN: i32 = 100;
+
+// whether a score is exact or only a bound because it fell outside the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScoreBound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+impl ScoreBound {
+    pub fn from_window(score: i32, alpha: i32, beta: i32) -> ScoreBound {
+        if score >= beta {
+            ScoreBound::Lower
+        } else if score <= alpha {
+            ScoreBound::Upper
+        } else {
+            ScoreBound::Exact
+        }
+    }
+
+    fn as_uci_str(self) -> &'static str {
+        match self {
+            ScoreBound::Exact => "",
+            ScoreBound::Lower => " lowerbound",
+            ScoreBound::Upper => " upperbound",
+        }
+    }
+}
+
+// a root move with its score and the pv that starts with it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PvLine {
+    pub score: i32,
+    pub bound: ScoreBound,
+    pub moves: Vec<Move>,
+}
+
+impl PvLine {
+    pub fn best_move(&self) -> Move {
+        self.moves[0]
+    }
+}
 
-pub type SearchResult = Result<(i32, Move), SearchError>;
+// the lines of a search from best to worst, there is always at least one
+pub type SearchResult = Result<Vec<PvLine>, SearchError>;
+
+// how a search reports its progress to the gui
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfoOutput {
+    Uci,
+    XBoard,
+    Silent,
+}
+
+// engine settings set through uci options
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub multipv: usize,
+    pub ponder: bool,
+    pub move_overhead: u128,
+    // castles are written as the king taking its own rook in chess960
+    pub chess960: bool,
+    // play below full strength, see Skill
+    pub skill_level: usize,
+    pub limit_strength: bool,
+    pub elo: usize,
+    pub output: InfoOutput,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            multipv: 1,
+            ponder: false,
+            move_overhead: DEFAULT_MOVE_OVERHEAD_MS,
+            chess960: false,
+            skill_level: MAX_SKILL_LEVEL,
+            limit_strength: false,
+            elo: MAX_ELO,
+            output: InfoOutput::Uci,
+        }
+    }
+}
 
 pub struct PVTable {
     table: Box<[Move]>,
@@ -55,48 +201,145 @@ impl PVTable {
         self.table.copy_within(next_ply_idx..end, ply_idx + 1)
     }
 
+    fn clear_ply(&mut self, ply: usize) {
+        self.table[PVTable::idx_from_ply(ply)] = NULL_MOVE;
+    }
+
     fn get_pv_line(&self) -> Vec<Move> {
         self.table
             .iter()
             .copied()
+            .take(MAX_DEPTH)
             .take_while(|m| *m != NULL_MOVE)
             .collect()
     }
 }
 
+// late move reductions by depth and the number of moves already searched
+struct ReductionTable {
+    table: Box<[[usize; LMR_MAX_MOVES]; MAX_DEPTH]>,
+}
+
+impl ReductionTable {
+    fn new() -> ReductionTable {
+        let mut table = Box::new([[0; LMR_MAX_MOVES]; MAX_DEPTH]);
+
+        for (depth, row) in table.iter_mut().enumerate().skip(1) {
+            for (moves, reduction) in row.iter_mut().enumerate().skip(1) {
+                let r = LMR_BASE + (depth as f64).ln() * (moves as f64).ln() / LMR_DIV;
+                *reduction = r as usize;
+            }
+        }
+
+        ReductionTable { table }
+    }
+
+    fn get(&self, depth: usize, moves_searched: usize) -> usize {
+        self.table[depth.min(MAX_DEPTH - 1)][moves_searched.min(LMR_MAX_MOVES - 1)]
+    }
+}
+
+// what the search keeps for each ply of the current line
+#[derive(Debug, Clone, Copy, Default)]
+struct StackEntry {
+    // a move left out of this node, used by the singular extension search
+    excluded: Option<Move>,
+    // the move being searched from this node, none for a null move
+    current: Option<Move>,
+}
+
 pub struct Searcher<T: TT> {
     aborted: bool,
-    start: Instant,
-    time_limit_ms: u128,
+    tm: TimeManager,
     root_depth: i32,
     pub ply: i32,
     c_mul: i32,
     pub tt: T,
     pub pv_table: PVTable,
-    pub km: KillerMoves,
-    pub hh: HistoryTable,
+    pub history: SearchHistory,
     prev_moves: PrevMoves,
     nodes: usize,
+    root_moves: Vec<Move>,
+    // nodes of every thread in the search, each searcher adds its own nodes every so often
+    node_counter: Arc<AtomicUsize>,
+    flushed_nodes: usize,
+    seldepth: i32,
+    root_move_number: usize,
+    // only the main searcher prints info while searching
+    is_main: bool,
+    last_info_ms: u128,
+    chess960: bool,
+    output: InfoOutput,
+    // the multipv lines asked for, a weakened search looks at more but does not show them
+    info_lines: usize,
+    // the ply straight after a null move, where another null move would just undo it
+    null_move_ply: Option<i32>,
+    verifying_null_move: bool,
+    reductions: ReductionTable,
+    stack: Box<[StackEntry; MAX_DEPTH]>,
 }
 
 impl<T: TT> Searcher<T> {
-    fn new(tt: T, prev_moves: PrevMoves, time_limit_ms: u128) -> Searcher<T> {
+    fn new(
+        tt: T,
+        prev_moves: PrevMoves,
+        tm: TimeManager,
+        limits: &SearchLimits,
+        options: &SearchOptions,
+        node_counter: Arc<AtomicUsize>,
+    ) -> Searcher<T> {
         Searcher {
             aborted: false,
-            start: Instant::now(),
-            time_limit_ms,
+            tm,
             root_depth: 0,
             ply: 0,
             c_mul: 0,
             tt,
             pv_table: PVTable::default(),
-            km: KillerMoves::new(),
-            hh: HistoryTable::new(),
+            history: SearchHistory::new(),
             prev_moves,
             nodes: 0,
+            root_moves: limits.searchmoves.clone(),
+            node_counter,
+            flushed_nodes: 0,
+            seldepth: 0,
+            root_move_number: 0,
+            is_main: true,
+            last_info_ms: 0,
+            chess960: options.chess960,
+            output: options.output,
+            info_lines: options.multipv,
+            null_move_ply: None,
+            verifying_null_move: false,
+            reductions: ReductionTable::new(),
+            stack: Box::new([StackEntry::default(); MAX_DEPTH]),
         }
     }
 
+    // the move played the given number of plies before this node, if it was a real move
+    pub fn prev_move(&self, plies: i32) -> Option<Move> {
+        if self.ply < plies {
+            return None;
+        }
+
+        self.stack[(self.ply - plies) as usize].current
+    }
+
+    // how well a quiet move has done, on its own and as a reply to the last two moves
+    pub fn quiet_history(&self, b: &Board, m: Move) -> i32 {
+        let history = self
+            .history
+            .hh
+            .get(b.ctm(), m.from() as usize, m.to() as usize);
+
+        [1, 2]
+            .into_iter()
+            .filter_map(|plies| self.prev_move(plies))
+            .fold(history, |history, prev| {
+                history + self.history.ch.get(prev, m)
+            })
+    }
+
     fn draft(&self) -> i32 {
         self.root_depth - self.ply
     }
@@ -105,20 +348,70 @@ impl<T: TT> Searcher<T> {
         self.c_mul = if b.ctm() == WHITE { 1 } else { -1 };
         self.ply = 0;
         self.root_depth = depth as i32;
-        self.nodes = 0;
     }
 
     fn has_aborted(&mut self) -> bool {
-        // check only every few thousand nodes
-        if self.nodes & 0xFFF == 0 {
-            self.aborted = self.time_limit_ms < self.start.elapsed().as_millis();
+        // check the clock only every few thousand nodes. The node limit is for all threads together,
+        // the nodes of the others are only seen once they flush them
+        if self.nodes & 0xFFF == 0 || self.tm.out_of_nodes(self.total_nodes()) {
+            self.flush_nodes();
+            self.aborted = self.tm.hard_stop(self.total_nodes());
+            self.write_heartbeat();
         }
 
         self.aborted
     }
 
+    fn flush_nodes(&mut self) {
+        self.node_counter
+            .fetch_add(self.nodes - self.flushed_nodes, Ordering::Relaxed);
+        self.flushed_nodes = self.nodes;
+    }
+
+    fn total_nodes(&self) -> usize {
+        self.node_counter.load(Ordering::Relaxed) + self.nodes - self.flushed_nodes
+    }
+
+    fn nps(&self) -> usize {
+        (self.total_nodes() as u128 * 1000 / self.tm.elapsed_ms().max(1)) as usize
+    }
+
+    fn write_heartbeat(&mut self) {
+        let time = self.tm.elapsed_ms();
+        let is_due = time >= self.last_info_ms + INFO_INTERVAL_MS;
+        if !self.is_main || self.output != InfoOutput::Uci || !is_due {
+            return;
+        }
+
+        self.last_info_ms = time;
+        println!(
+            "info nodes {} nps {} hashfull {} time {}",
+            self.total_nodes(),
+            self.nps(),
+            self.tt.hashfull(),
+            time
+        );
+    }
+
+    fn write_currmove(&mut self, m: Move) {
+        self.root_move_number += 1;
+
+        if self.is_main
+            && self.output == InfoOutput::Uci
+            && self.tm.elapsed_ms() >= CURRMOVE_DELAY_MS
+        {
+            println!(
+                "info depth {} currmove {} currmovenumber {}",
+                self.root_depth,
+                m.as_uci_notation(self.chess960),
+                self.root_move_number
+            );
+        }
+    }
+
     fn push_ply(&mut self) {
         self.ply += 1;
+        self.seldepth = self.seldepth.max(self.ply);
         self.c_mul = -self.c_mul;
     }
 
@@ -127,13 +420,15 @@ impl<T: TT> Searcher<T> {
         self.c_mul = -self.c_mul;
     }
 
-    fn store_tt(&mut self, hash: u64, score: EntryScore, bm: Option<Move>) {
-        // do not store anything after abort
-        if self.aborted {
+    // entries are stored with the depth that was searched, which is less than the draft once a
+    // node has been reduced
+    fn store_tt(&mut self, hash: u64, score: EntryScore, bm: Option<Move>, depth: usize) {
+        // do not store anything after abort, or for a node searched without one of its moves
+        if self.aborted || self.stack[self.ply as usize].excluded.is_some() {
             return;
         }
 
-        self.tt.insert(hash, score, bm, self.draft());
+        self.tt.insert(hash, score, bm, depth as i32);
     }
 
     fn try_move(
@@ -151,10 +446,18 @@ impl<T: TT> Searcher<T> {
             return None;
         }
 
+        if self.ply == 0 {
+            self.write_currmove(m);
+        }
+
+        // checks are searched a ply deeper so forcing lines are not cut off at the horizon
+        let extension = (self.can_extend() && is_in_check(&b)) as usize;
+
+        self.stack[self.ply as usize].current = Some(m);
         self.push_ply();
         self.prev_moves.add(b.hash());
 
-        let score = -self.pvs(&b, -beta, -alpha, depth - 1);
+        let score = -self.pvs(&b, -beta, -alpha, depth + extension - 1);
 
         self.pop_ply();
         self.prev_moves.remove(b.hash());
@@ -172,26 +475,37 @@ impl<T: TT> Searcher<T> {
         self.try_move(b, m, alpha, alpha + 1, depth)
     }
 
+    // searches the root moves (or only the searchmoves if there are any), skipping any excluded
+    // moves such as the moves of earlier multipv lines. A score outside the window is an error, on
+    // a fail high the pv table holds the move that failed high
     pub fn root_pvs(
         &mut self,
         b: &Board,
         mut alpha: i32,
         beta: i32,
         depth: usize,
-    ) -> Option<(i32, Move)> {
+        excluded: &[Move],
+    ) -> Result<(i32, Move), SearchError> {
         self.init_search(b, depth);
+        self.root_move_number = 0;
 
-        let mut ml = ScoredMoveList::new(b, self, depth);
-        gen_moves(b, &mut ml, is_in_check(b));
+        let mut ml = ScoredMoveList::new(b, self, depth, is_in_check(b));
 
         let mut best_res = None;
+        let mut has_moved = false;
         let mut tt_entry_score = EntryScore::new_alpha(alpha, self.ply);
 
-        for m in ml {
+        while let Some(m) = ml.next_move(b, self) {
+            if excluded.contains(&m) || !self.is_root_move(m) {
+                continue;
+            }
+
             let Some(score) = self.try_move(b, m, alpha, beta, depth) else {
                 continue;
             };
 
+            has_moved = true;
+
             if score > alpha {
                 alpha = score;
                 best_res = Some((alpha, m));
@@ -206,89 +520,222 @@ impl<T: TT> Searcher<T> {
                     Some(m),
                     self.draft(),
                 );
-                best_res = Some((beta, m));
+                self.flush_nodes();
 
-                return best_res;
+                return Err(SearchError::FailHigh);
             }
         }
 
-        self.store_tt(b.hash(), tt_entry_score, best_res.map(|b| b.1));
+        self.store_tt(b.hash(), tt_entry_score, best_res.map(|b| b.1), depth);
+        self.flush_nodes();
+
+        match best_res {
+            Some(res) => Ok(res),
+            None if has_moved => Err(SearchError::FailLow),
+            None => Err(SearchError::NoMove),
+        }
+    }
+
+    fn is_root_move(&self, m: Move) -> bool {
+        self.root_moves.is_empty() || self.root_moves.contains(&m)
+    }
+
+    // the pv table stops at tt cutoffs, so follow the tt best moves to fill out the rest of the line.
+    // A mating line is followed all the way to the mate
+    fn pv_line(&self, b: &Board, score: i32, bound: ScoreBound) -> PvLine {
+        let mut moves = self.pv_table.get_pv_line();
+        let mut board = moves.iter().fold(*b, |board, m| board.copy_make(*m));
+
+        let length = match score {
+            score if is_mate_score(score) => (MATED - score.abs()) as usize,
+            _ => self.root_depth as usize,
+        };
+
+        while moves.len() < length {
+            let Some(m) = self
+                .tt
+                .get_bestmove(board.hash())
+                .filter(|m| is_legal(&board, *m))
+            else {
+                break;
+            };
+
+            board = board.copy_make(m);
+            moves.push(m);
+        }
 
-        best_res
+        PvLine {
+            score,
+            bound,
+            moves,
+        }
     }
 
-    fn pvs(&mut self, b: &Board, mut alpha: i32, beta: i32, depth: usize) -> i32 {
+    fn pvs(&mut self, b: &Board, mut alpha: i32, mut beta: i32, depth: usize) -> i32 {
         if self.has_aborted() {
             return MIN_SCORE;
         }
 
         self.nodes += 1;
 
+        // stop the parent from copying a stale line if this node returns before setting a pv
+        self.pv_table.clear_ply(self.ply as usize);
+
         if depth == 0 {
             let q_score = self.q_search(b, alpha, beta);
-            self.tt.insert(
-                b.hash(),
-                EntryScore::new_pv(q_score, self.ply),
-                None,
-                self.draft(),
-            );
+            self.tt
+                .insert(b.hash(), EntryScore::new_pv(q_score, self.ply), None, 0);
             return q_score;
         }
 
+        // the ply limit can only be reached through extensions
+        if self.ply as usize >= MAX_DEPTH - 1 {
+            return eval(b, self.c_mul);
+        }
+
+        // no line from here can beat being mated now or mating next move, so a window outside
+        // those scores is already decided
+        alpha = alpha.max(CHECKMATE + self.ply);
+        beta = beta.min(MATED - self.ply - 1);
+        if alpha >= beta {
+            return alpha;
+        }
+
+        // the tt entry is for the whole node, not the node without the excluded move
+        let excluded = self.stack[self.ply as usize].excluded;
+
         if let Some(score) = self
             .tt
-            .get_score(b.hash(), self.draft(), self.ply, alpha, beta)
+            .get_score(b.hash(), depth as i32, self.ply, alpha, beta)
+            .filter(|_| excluded.is_none())
         {
             return score;
         }
 
-        // TODO is this needed?
-        self.pv_table.table[PVTable::idx_from_ply(self.ply as usize)] = NULL_MOVE;
-
         let in_check = is_in_check(b);
+        let is_pv = alpha + 1 < beta;
+
+        // without a tt move the move ordering is poor and the search is likely to be slow, so a
+        // quicker search is done instead
+        let iir_min_depth = if is_pv {
+            IIR_PV_MIN_DEPTH
+        } else {
+            IIR_MIN_DEPTH
+        };
+        let depth = if depth >= iir_min_depth
+            && excluded.is_none()
+            && self.tt.get_bestmove(b.hash()).is_none()
+        {
+            depth - 1
+        } else {
+            depth
+        };
+
+        // the static eval is meaningless in check, and pv nodes and excluded move searches are
+        // never pruned
+        let static_eval = if in_check || is_pv || excluded.is_some() {
+            None
+        } else {
+            Some(eval(b, self.c_mul))
+        };
+
+        if let Some(static_eval) = static_eval {
+            if reverse_futility_cutoff(static_eval, beta, depth) {
+                return beta;
+            }
+
+            if let Some(score) = self.razor(b, static_eval, alpha, beta, depth) {
+                return score;
+            }
+
+            if self.null_move_cutoff(b, static_eval, beta, depth) {
+                return beta;
+            }
+
+            if self.probcut(b, static_eval, beta, depth) {
+                return beta;
+            }
+        }
+
+        let futile = static_eval.is_some_and(|e| is_futile(e, alpha, depth));
+
+        let singular_move = self.singular_move(b, depth);
 
         let mut best_move = None;
         let mut tt_entry_score = EntryScore::new_alpha(alpha, self.ply);
 
-        let mut ml = ScoredMoveList::new(b, self, depth);
-        gen_moves(b, &mut ml, in_check);
+        let mut ml = ScoredMoveList::new(b, self, depth, in_check);
 
         let mut found_pv = false;
         let mut has_moved = false;
+        let mut moves_searched = 0;
+        let mut quiets_tried = StackMoveList::<MAX_MOVES>::new();
 
-        for m in ml {
-            let score = if !found_pv {
-                let Some(score) = self.try_move(b, m, alpha, beta, depth) else {
+        while let Some(m) = ml.next_move(b, self) {
+            if Some(m) == excluded {
+                continue;
+            }
+
+            // keep at least one move so a pruned node still can't be mistaken for a mate, and
+            // don't prune the defences of a mated side as a mate has to hold against all of them
+            if has_moved
+                && static_eval.is_some()
+                && !is_mate_score(alpha)
+                && prune_move(b, m, depth, moves_searched, futile)
+            {
+                continue;
+            }
+
+            let reduction = self.late_move_reduction(b, m, depth, moves_searched, in_check, is_pv);
+            let move_depth = depth + (Some(m) == singular_move) as usize;
+
+            let score = if !found_pv && reduction == 0 {
+                let Some(score) = self.try_move(b, m, alpha, beta, move_depth) else {
                     continue;
                 };
 
-                has_moved = true;
                 score
             } else {
-                let Some(mut score) = self.try_null_window_move(b, m, alpha, depth) else {
+                let Some(mut score) =
+                    self.try_null_window_move(b, m, alpha, move_depth - reduction)
+                else {
                     continue;
                 };
 
+                // it is safe to unwrap the re-searches as the move is legal at this point
+                if score > alpha && reduction > 0 {
+                    score = self.try_null_window_move(b, m, alpha, move_depth).unwrap();
+                }
+
                 if score > alpha {
-                    // it is safe to unwrap here as the move is legal at this point
-                    score = self.try_move(b, m, alpha, beta, depth).unwrap();
+                    score = self.try_move(b, m, alpha, beta, move_depth).unwrap();
                 }
 
                 score
             };
 
+            has_moved = true;
+            moves_searched += 1;
+
             if score >= beta {
-                self.store_tt(b.hash(), EntryScore::new_beta(beta, self.ply), Some(m));
+                self.store_tt(
+                    b.hash(),
+                    EntryScore::new_beta(beta, self.ply),
+                    Some(m),
+                    depth,
+                );
 
                 if m.move_type() == MoveType::Quiet {
-                    self.km.add(m, depth);
-                    self.hh
-                        .insert(b.ctm(), m.from() as usize, m.to() as usize, depth);
+                    self.update_quiet_history(b, m, depth, quiets_tried);
                 }
 
                 return beta;
             }
 
+            if m.move_type() == MoveType::Quiet {
+                quiets_tried.add_move(m);
+            }
+
             if score > alpha {
                 alpha = score;
                 best_move = Some(m);
@@ -298,6 +745,11 @@ impl<T: TT> Searcher<T> {
             }
         }
 
+        // with the only move excluded this node is no mate, it just has nothing better than alpha
+        if !has_moved && excluded.is_some() {
+            return alpha;
+        }
+
         if !has_moved {
             alpha = if in_check {
                 CHECKMATE + self.ply
@@ -308,24 +760,241 @@ impl<T: TT> Searcher<T> {
             tt_entry_score = EntryScore::new_pv(alpha, self.ply);
         }
 
-        self.store_tt(b.hash(), tt_entry_score, best_move);
+        self.store_tt(b.hash(), tt_entry_score, best_move, depth);
         alpha
     }
 
-    fn try_q_move(
+    // extensions stop once a line is twice as long as the root depth
+    fn can_extend(&self) -> bool {
+        self.ply < self.root_depth * 2
+    }
+
+    // the tt move is singular, and worth extending, if a reduced search of every other move cannot
+    // get close to its score
+    fn singular_move(&mut self, b: &Board, depth: usize) -> Option<Move> {
+        let ply = self.ply as usize;
+
+        if depth < SE_MIN_DEPTH
+            || ply == 0
+            || !self.can_extend()
+            || self.stack[ply].excluded.is_some()
+        {
+            return None;
+        }
+
+        let entry = self.tt.get(b.hash())?;
+        let tt_score = entry.score.lower_bound(self.ply)?;
+
+        if entry.best == NULL_MOVE
+            || (entry.draft as i32) < (depth - SE_TT_DEPTH_MARGIN) as i32
+            || is_mate_score(tt_score)
+        {
+            return None;
+        }
+
+        let singular_beta = tt_score - SE_MARGIN * depth as i32;
+
+        self.stack[ply].excluded = Some(entry.best);
+        let score = self.pvs(b, singular_beta - 1, singular_beta, (depth - 1) / 2);
+        self.stack[ply].excluded = None;
+
+        (score < singular_beta).then_some(entry.best)
+    }
+
+    // late quiet moves are unlikely to be best after move ordering, so they are searched shallower
+    // first. Each LMR_HISTORY_DIV of history takes a ply off and each below zero adds one, up to
+    // two plies either way, so a move without history keeps the base reduction
+    fn late_move_reduction(
+        &self,
+        b: &Board,
+        m: Move,
+        depth: usize,
+        moves_searched: usize,
+        in_check: bool,
+        is_pv: bool,
+    ) -> usize {
+        if depth < LMR_MIN_DEPTH
+            || moves_searched < LMR_MIN_MOVES + is_pv as usize
+            || in_check
+            || !is_quiet_move(b, m)
+        {
+            return 0;
+        }
+
+        let mut reduction = self.reductions.get(depth, moves_searched);
+        let history = (self.quiet_history(b, m) / LMR_HISTORY_DIV).clamp(-2, 2);
+        reduction = (reduction as i32 - history).max(0) as usize;
+
+        if is_pv || self.history.km.get_move_priority(m, depth).is_some() {
+            reduction = reduction.saturating_sub(1);
+        }
+
+        // always leave at least one ply to search
+        reduction.min(depth - 2)
+    }
+
+    // a quiet move that caused a cutoff is a killer, the countermove of the move before it and
+    // gains history as a reply to the last two moves. The quiets searched before it lose as much
+    fn update_quiet_history(
         &mut self,
-        board: &Board,
+        b: &Board,
         m: Move,
+        depth: usize,
+        quiets_tried: StackMoveList<MAX_MOVES>,
+    ) {
+        self.history.km.add(m, depth);
+
+        if let Some(prev) = self.prev_move(1) {
+            self.history.cm.insert(prev, m);
+        }
+
+        let bonus = history_bonus(depth);
+        self.update_history(b, m, bonus);
+
+        for quiet in quiets_tried {
+            self.update_history(b, quiet, -bonus);
+        }
+    }
+
+    fn update_history(&mut self, b: &Board, m: Move, bonus: i32) {
+        self.history
+            .hh
+            .update(b.ctm(), m.from() as usize, m.to() as usize, bonus);
+
+        for plies in [1, 2] {
+            if let Some(prev) = self.prev_move(plies) {
+                self.history.ch.update(prev, m, bonus);
+            }
+        }
+    }
+
+    // if passing the turn still fails high, any real move is very likely to as well. Side to move
+    // has to have pieces other than pawns as pawn endgames are full of zugzwang
+    fn null_move_cutoff(&mut self, b: &Board, static_eval: i32, beta: i32, depth: usize) -> bool {
+        if is_mate_score(beta)
+            || depth < NMP_MIN_DEPTH
+            || self.verifying_null_move
+            || self.null_move_ply == Some(self.ply)
+            || !has_non_pawn_material(b)
+            || static_eval < beta
+        {
+            return false;
+        }
+
+        let reduced_depth = depth.saturating_sub(1 + NMP_REDUCTION + depth / NMP_DEPTH_DIV);
+        let null_b = b.copy_make_null();
+
+        let prev_null_move_ply = self.null_move_ply.replace(self.ply + 1);
+        self.stack[self.ply as usize].current = None;
+        self.push_ply();
+        let score = -self.pvs(&null_b, -beta, -beta + 1, reduced_depth);
+        self.pop_ply();
+        self.null_move_ply = prev_null_move_ply;
+
+        if score < beta {
+            return false;
+        }
+
+        if depth < NMP_VERIFY_DEPTH {
+            return true;
+        }
+
+        self.verifying_null_move = true;
+        let score = self.pvs(b, beta - 1, beta, reduced_depth);
+        self.verifying_null_move = false;
+
+        score >= beta
+    }
+
+    // a capture that still beats beta by a margin in a shallow search will very likely beat beta
+    // in the full search. Each capture first has to hold in qsearch, which is much cheaper
+    fn probcut(&mut self, b: &Board, static_eval: i32, beta: i32, depth: usize) -> bool {
+        if depth < PROBCUT_MIN_DEPTH || is_mate_score(beta) {
+            return false;
+        }
+
+        let probcut_beta = beta + PROBCUT_MARGIN;
+        let probcut_depth = depth - PROBCUT_REDUCTION;
+
+        // no point searching if the tt already knows the position stays below probcut beta
+        if self.tt.get(b.hash()).is_some_and(|entry| {
+            entry.draft >= probcut_depth as i8
+                && entry
+                    .score
+                    .upper_bound(self.ply)
+                    .is_some_and(|score| score < probcut_beta)
+        }) {
+            return false;
+        }
+
+        let mut ml = QSearchMoveList::<'_, T, 100>::new(b, self, 0);
+        gen_all_attacks(b, &mut ml);
+
+        for m in ml {
+            if !m.move_type().is_cap() || !see_ge(b, m, probcut_beta - static_eval) {
+                continue;
+            }
+
+            let Some(mut score) = self.try_q_move(b, m, probcut_beta - 1, probcut_beta) else {
+                continue;
+            };
+
+            // the full search also checks repetitions and the fifty move rule, which the qsearch
+            // doesn't, so it can still turn the move down
+            if score >= probcut_beta {
+                let Some(full_score) =
+                    self.try_null_window_move(b, m, probcut_beta - 1, probcut_depth + 1)
+                else {
+                    continue;
+                };
+
+                score = full_score;
+            }
+
+            if score >= probcut_beta {
+                self.store_tt(
+                    b.hash(),
+                    EntryScore::new_beta(probcut_beta, self.ply),
+                    Some(m),
+                    probcut_depth + 1,
+                );
+
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // a node far below alpha is only searched further if qsearch finds a tactic to climb back
+    fn razor(
+        &mut self,
+        b: &Board,
+        static_eval: i32,
         alpha: i32,
         beta: i32,
-        eval: i32,
+        depth: usize,
     ) -> Option<i32> {
+        if depth > RAZOR_MAX_DEPTH
+            || is_mate_score(alpha)
+            || static_eval + RAZOR_BASE + RAZOR_MARGIN * (depth as i32) >= alpha
+        {
+            return None;
+        }
+
+        let score = self.q_search(b, alpha, beta);
+        (score <= alpha).then_some(alpha)
+    }
+
+    fn try_q_move(&mut self, board: &Board, m: Move, alpha: i32, beta: i32) -> Option<i32> {
         let b = board.copy_make(m);
 
-        if moved_into_check(&b, m) || delta_prune(&b, alpha, eval, m) {
+        if moved_into_check(&b, m) {
             return None;
         }
 
+        // a check evasion below is searched by pvs, which orders and learns from the moves before it
+        self.stack[self.ply as usize].current = Some(m);
         self.push_ply();
         let score = -self.q_search(&b, -beta, -alpha);
         self.pop_ply();
@@ -380,7 +1049,7 @@ impl<T: TT> Searcher<T> {
                 return MATED - self.ply;
             }
 
-            let Some(score) = self.try_q_move(b, m, alpha, beta, eval) else {
+            let Some(score) = self.try_q_move(b, m, alpha, beta) else {
                 continue;
             };
 
@@ -397,58 +1066,280 @@ impl<T: TT> Searcher<T> {
     }
 }
 
-fn delta_prune(b: &Board, alpha: i32, eval: i32, m: Move) -> bool {
-    eval + PIECE_VALUES[m.xpiece() as usize] + 200 < alpha
-        && !m.move_type().is_promo()
-        && (b.all_occ() ^ b.pawns(WHITE) ^ b.pawns(BLACK)).count_ones() > 4
+fn has_non_pawn_material(b: &Board) -> bool {
+    b.occ(b.ctm()) ^ b.pawns(b.ctm()) ^ b.king(b.ctm()) != 0
+}
+
+// quiet moves that don't give check are the ones pruned and reduced near the leaves
+fn is_quiet_move(b: &Board, m: Move) -> bool {
+    matches!(m.move_type(), MoveType::Quiet | MoveType::Double) && !is_in_check(&b.copy_make(m))
+}
+
+// a static eval far above beta is unlikely to drop below it within a few plies
+fn reverse_futility_cutoff(static_eval: i32, beta: i32, depth: usize) -> bool {
+    depth <= RFP_MAX_DEPTH
+        && !is_mate_score(beta)
+        && static_eval - RFP_MARGIN * (depth as i32) >= beta
+}
+
+// a quiet move can't gain enough near the leaves to bring a static eval far below alpha up to it
+fn is_futile(static_eval: i32, alpha: i32, depth: usize) -> bool {
+    depth <= FP_MAX_DEPTH
+        && !is_mate_score(alpha)
+        && static_eval + FP_BASE + FP_MARGIN * (depth as i32) <= alpha
 }
 
-pub fn iterative_deepening(board: &Board, tt: impl TT, prev_moves: PrevMoves) -> SearchResult {
-    let mut s = Searcher::new(tt, prev_moves, TIME_LIMIT_MS);
+// late quiet moves, quiet moves at futile nodes and moves that lose too much material are skipped
+// near the leaves
+fn prune_move(b: &Board, m: Move, depth: usize, moves_searched: usize, futile: bool) -> bool {
+    let is_quiet = is_quiet_move(b, m);
+
+    if is_quiet && (futile || depth <= LMP_MAX_DEPTH && moves_searched >= LMP_BASE + depth * depth)
+    {
+        return true;
+    }
 
-    let mut res = None;
+    let margin = if is_quiet {
+        SEE_QUIET_MARGIN
+    } else if m.move_type().is_cap() && !m.move_type().is_promo() {
+        SEE_CAPTURE_MARGIN
+    } else {
+        return false;
+    };
 
-    for depth in 1..MAX_DEPTH {
-        let alpha_window = MIN_SCORE;
-        let beta_window = MAX_SCORE;
+    depth <= SEE_PRUNE_MAX_DEPTH && !see_ge(b, m, -margin * depth as i32)
+}
 
-        let iter_res = s.root_pvs(board, alpha_window, beta_window, depth);
+pub fn iterative_deepening(
+    board: &Board,
+    tt: impl TT,
+    prev_moves: PrevMoves,
+    history: &mut SearchHistory,
+    limits: &SearchLimits,
+    options: &SearchOptions,
+    control: SearchControl,
+) -> SearchResult {
+    let mut skill = Skill::new(options);
+    let limits = &skill.limit(limits);
+    let multipv = skill.multipv(options.multipv);
 
-        if s.has_aborted() {
+    let node_counter = control.node_counter();
+    let tm = TimeManager::new(limits, board.ctm(), options, control);
+    let mut s = Searcher::new(tt, prev_moves, tm, limits, options, node_counter);
+    s.history = std::mem::take(history);
+
+    let mut res: Option<Vec<PvLine>> = None;
+
+    for depth in 1..=s.tm.max_depth() {
+        let prev_lines = res.as_deref().unwrap_or_default();
+        let Some(lines) = search_lines(&mut s, board, depth, multipv, prev_lines) else {
+            break;
+        };
+
+        write_info(&s, &lines, depth);
+
+        if lines.is_empty() {
             break;
         }
 
-        res = iter_res;
+        let mate_found = proves_mate(&s.tm, &lines);
+        res = Some(lines);
 
-        write_info(&s, board, res, depth);
+        if mate_found || s.tm.soft_stop(s.total_nodes()) {
+            break;
+        }
     }
 
-    res.ok_or(SearchError::NoMove)
+    *history = s.history;
+
+    res.map(|lines| skill.pick_line(lines))
+        .ok_or(SearchError::NoMove)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn lazy_smp(
     board: &Board,
     tt: Arc<SmpTTable>,
     prev_moves: PrevMoves,
+    history: &mut SearchHistory,
+    limits: &SearchLimits,
+    options: &SearchOptions,
+    control: SearchControl,
     num_threads: usize,
 ) -> SearchResult {
-    let mut res = None;
+    let mut res: Option<Vec<PvLine>> = None;
+
+    let mut skill = Skill::new(options);
+    let limits = &skill.limit(limits);
+    let multipv = skill.multipv(options.multipv);
+
+    let node_counter = control.node_counter();
+    let tm = TimeManager::new(limits, board.ctm(), options, control);
+    let mut smp = LazySmp::new(
+        tt,
+        prev_moves,
+        tm,
+        limits,
+        options,
+        node_counter,
+        num_threads,
+    );
+    smp.set_history(std::mem::take(history));
+
+    for depth in 1..=smp.main.tm.max_depth() {
+        let prev_lines = res.as_deref().unwrap_or_default();
+        let Some(lines) = search_lines(&mut smp, board, depth, multipv, prev_lines) else {
+            break;
+        };
 
-    let mut smp = LazySmp::new(tt, prev_moves, TIME_LIMIT_MS, num_threads);
+        write_info(&smp.main, &lines, depth);
 
-    for depth in 1..MAX_DEPTH {
-        let iter_res = smp.run_iter(board, depth);
+        if lines.is_empty() {
+            break;
+        }
+
+        let mate_found = proves_mate(&smp.main.tm, &lines);
+        res = Some(lines);
 
-        if iter_res.is_none() {
+        if mate_found || smp.main.tm.soft_stop(smp.main.total_nodes()) {
             break;
         }
+    }
+
+    *history = smp.main.history;
+
+    res.map(|lines| skill.pick_line(lines))
+        .ok_or(SearchError::NoMove)
+}
+
+// a root search over one window by one searcher or by every lazy smp thread. The lines are
+// always built from the main searcher
+trait RootSearch {
+    type Table: TT;
+
+    fn main(&mut self) -> &mut Searcher<Self::Table>;
+
+    fn root_search(
+        &mut self,
+        b: &Board,
+        alpha: i32,
+        beta: i32,
+        depth: usize,
+        excluded: &[Move],
+    ) -> Result<(i32, Move), SearchError>;
+}
+
+impl<T: TT> RootSearch for Searcher<T> {
+    type Table = T;
+
+    fn main(&mut self) -> &mut Searcher<T> {
+        self
+    }
+
+    fn root_search(
+        &mut self,
+        b: &Board,
+        alpha: i32,
+        beta: i32,
+        depth: usize,
+        excluded: &[Move],
+    ) -> Result<(i32, Move), SearchError> {
+        self.root_pvs(b, alpha, beta, depth, excluded)
+    }
+}
+
+// runs a root search for each of the multipv lines, returns none if the search was aborted. Each
+// line is searched in a window around its score in the last iteration, which is widened on the
+// side it failed until the score lands inside it
+fn search_lines<R: RootSearch>(
+    r: &mut R,
+    b: &Board,
+    depth: usize,
+    multipv: usize,
+    prev_lines: &[PvLine],
+) -> Option<Vec<PvLine>> {
+    let mut lines: Vec<PvLine> = Vec::with_capacity(multipv);
+    r.main().seldepth = 0;
+
+    'lines: while lines.len() < multipv {
+        let excluded: Vec<Move> = lines.iter().map(PvLine::best_move).collect();
+        let prev_line = prev_lines.get(lines.len());
+        let mut delta = ASPIRATION_WINDOW;
+
+        let (mut alpha, mut beta) = match prev_line {
+            Some(line) if depth >= ASPIRATION_MIN_DEPTH && !is_mate_score(line.score) => {
+                (line.score - delta, line.score + delta)
+            }
+            _ => (MIN_SCORE, MAX_SCORE),
+        };
+
+        loop {
+            let res = r.root_search(b, alpha, beta, depth, &excluded);
+            let s = r.main();
+
+            if s.has_aborted() {
+                return None;
+            }
+
+            let multipv_idx = lines.len() + 1;
+            delta *= 2;
+
+            match res {
+                Ok((score, _)) => {
+                    let bound = ScoreBound::from_window(score, alpha, beta);
+                    lines.push(s.pv_line(b, score, bound));
+                    break;
+                }
+                // there are no more legal moves to search
+                Err(SearchError::NoMove) => break 'lines,
+                // nothing reached alpha so there is no new pv, the last one is reported instead
+                Err(SearchError::FailLow) => {
+                    let line = PvLine {
+                        score: alpha,
+                        bound: ScoreBound::Upper,
+                        moves: prev_line.map_or_else(Vec::new, |line| line.moves.clone()),
+                    };
+                    write_line_info(s, &line, multipv_idx, depth);
+                    s.tm.extend_after_fail_low();
+
+                    alpha = widen_window(alpha, -delta);
+                }
+                Err(SearchError::FailHigh) => {
+                    let line = s.pv_line(b, beta, ScoreBound::Lower);
+                    write_line_info(s, &line, multipv_idx, depth);
+
+                    beta = widen_window(beta, delta);
+                }
+            }
+        }
+    }
 
-        res = iter_res;
+    rank_lines(&mut lines);
+    Some(lines)
+}
 
-        write_info(&smp.main, board, res, depth);
+// once the window has been widened a few times it is opened all the way on that side
+fn widen_window(bound: i32, delta: i32) -> i32 {
+    if delta.abs() > ASPIRATION_MAX_DELTA {
+        if delta < 0 {
+            MIN_SCORE
+        } else {
+            MAX_SCORE
+        }
+    } else {
+        (bound + delta).clamp(MIN_SCORE, MAX_SCORE)
     }
+}
+
+// only an exact score proves a mate, a bound just says it is at least that good
+fn proves_mate(tm: &TimeManager, lines: &[PvLine]) -> bool {
+    lines
+        .first()
+        .is_some_and(|line| line.bound == ScoreBound::Exact && tm.mate_found(line.score))
+}
 
-    res.ok_or(SearchError::NoMove)
+fn is_mate_score(score: i32) -> bool {
+    score >= MATED - MAX_DEPTH as i32 || score <= CHECKMATE + MAX_DEPTH as i32
 }
 
 struct LazySmp {
@@ -460,55 +1351,143 @@ impl LazySmp {
     fn new(
         tt: Arc<SmpTTable>,
         prev_moves: PrevMoves,
-        time_limit_ms: u128,
+        tm: TimeManager,
+        limits: &SearchLimits,
+        options: &SearchOptions,
+        node_counter: Arc<AtomicUsize>,
         num_threads: usize,
     ) -> LazySmp {
+        let new_searcher = |is_main| {
+            let mut s = Searcher::new(
+                tt.clone(),
+                prev_moves.clone(),
+                tm.clone(),
+                limits,
+                options,
+                node_counter.clone(),
+            );
+            s.is_main = is_main;
+            s
+        };
+
         LazySmp {
-            main: Searcher::new(tt.clone(), prev_moves.clone(), time_limit_ms),
-            helpers: (1..num_threads)
-                .map(|_| Searcher::new(tt.clone(), prev_moves.clone(), time_limit_ms))
-                .collect(),
+            main: new_searcher(true),
+            helpers: (1..num_threads).map(|_| new_searcher(false)).collect(),
+        }
+    }
+
+    // every helper starts from a copy of the history, only the main searcher's is kept
+    fn set_history(&mut self, history: SearchHistory) {
+        for h in self.helpers.iter_mut() {
+            h.history = history.clone();
         }
+
+        self.main.history = history;
     }
+}
 
-    fn run_iter(&mut self, board: &Board, depth: usize) -> Option<(i32, Move)> {
-        let alpha_window = MIN_SCORE;
-        let beta_window = MAX_SCORE;
+impl RootSearch for LazySmp {
+    type Table = Arc<SmpTTable>;
 
-        let mut iter_res = None;
+    fn main(&mut self) -> &mut Searcher<Arc<SmpTTable>> {
+        &mut self.main
+    }
+
+    // the helpers search the same window to fill the shared tt, only the main result is used
+    fn root_search(
+        &mut self,
+        b: &Board,
+        alpha: i32,
+        beta: i32,
+        depth: usize,
+        excluded: &[Move],
+    ) -> Result<(i32, Move), SearchError> {
         std::thread::scope(|scope| {
             for h in self.helpers.iter_mut() {
-                scope.spawn(|| h.root_pvs(board, alpha_window, beta_window, depth));
+                scope.spawn(move || h.root_pvs(b, alpha, beta, depth, excluded));
             }
 
-            let res = self.main.root_pvs(board, alpha_window, beta_window, depth);
+            self.main.root_pvs(b, alpha, beta, depth, excluded)
+        })
+    }
+}
+
+// later lines can score higher than earlier ones as the search is not perfectly stable
+fn rank_lines(lines: &mut [PvLine]) {
+    lines.sort_by_key(|line| std::cmp::Reverse(line.score));
+}
 
-            if !self.main.has_aborted() {
-                iter_res = res
-            }
-        });
+// mate scores are given in moves rather than plies
+pub fn uci_score(score: i32) -> String {
+    match score {
+        score if score >= MATED - MAX_DEPTH as i32 => format!("mate {}", (MATED - score + 1) / 2),
+        score if score <= CHECKMATE + MAX_DEPTH as i32 => {
+            format!("mate {}", -(score - CHECKMATE) / 2)
+        }
+        score => format!("cp {}", score),
+    }
+}
 
-        iter_res
+// xboard reads a mate in n as 100000 + n
+fn xboard_score(score: i32) -> i32 {
+    match score {
+        score if score >= MATED - MAX_DEPTH as i32 => 100000 + (MATED - score + 1) / 2,
+        score if score <= CHECKMATE + MAX_DEPTH as i32 => -100000 - (score - CHECKMATE) / 2,
+        score => score,
     }
 }
 
-fn write_info<T: TT>(s: &Searcher<T>, _b: &Board, res: Option<(i32, Move)>, depth: usize) {
-    let pv_str =
-        // s.tt.get_full_pv(b)
-        s.pv_table.get_pv_line()
-            .iter()
-            .fold(String::new(), |pv, m| pv + &m.as_uci_string() + " ");
+fn write_info<T: TT>(s: &Searcher<T>, lines: &[PvLine], depth: usize) {
+    for (i, line) in lines.iter().enumerate() {
+        write_line_info(s, line, i + 1, depth);
+    }
+}
 
-    // dbg!(&s.pv_table.table[..10]);
+fn write_line_info<T: TT>(s: &Searcher<T>, line: &PvLine, multipv: usize, depth: usize) {
+    match s.output {
+        _ if multipv > s.info_lines => {}
+        InfoOutput::Uci => write_uci_info(s, line, multipv, depth),
+        // xboard has no multipv, so only the best line is shown
+        InfoOutput::XBoard if multipv == 1 => write_xboard_info(s, line, depth),
+        _ => {}
+    }
+}
 
-    let (score, _) = res.unwrap_or((MIN_SCORE, NULL_MOVE));
+// time is in centiseconds
+fn write_xboard_info<T: TT>(s: &Searcher<T>, line: &PvLine, depth: usize) {
+    println!(
+        "{} {} {} {} {}",
+        depth,
+        xboard_score(line.score),
+        s.tm.elapsed_ms() / 10,
+        s.total_nodes(),
+        pv_string(s, line)
+    );
+}
 
-    let nps = s.nodes as f64 / s.start.elapsed().as_secs_f64();
+fn write_uci_info<T: TT>(s: &Searcher<T>, line: &PvLine, multipv: usize, depth: usize) {
     println!(
-        "info depth {} score cp {} nps {:.0} pv {}",
-        depth, score, nps, pv_str
+        "info depth {} seldepth {} multipv {} score {}{} nodes {} nps {} hashfull {} tbhits 0 \
+            time {} pv {}",
+        depth,
+        s.seldepth.max(depth as i32),
+        multipv,
+        uci_score(line.score),
+        line.bound.as_uci_str(),
+        s.total_nodes(),
+        s.nps(),
+        s.tt.hashfull(),
+        s.tm.elapsed_ms(),
+        pv_string(s, line)
     )
 }
+
+fn pv_string<T: TT>(s: &Searcher<T>, line: &PvLine) -> String {
+    line.moves.iter().fold(String::new(), |pv, m| {
+        pv + &m.as_uci_notation(s.chess960) + " "
+    })
+}
+
 #[test]
 fn pv_table_sets_pv_line() {
     crate::init();
@@ -527,3 +1506,431 @@ fn pv_table_sets_pv_line() {
         assert_eq!(pv_table.get(ply), *m);
     }
 }
+
+#[test]
+fn multipv_returns_distinct_ranked_lines() {
+    use crate::tt::NoTTable;
+
+    crate::init();
+
+    let options = SearchOptions {
+        multipv: 3,
+        ..Default::default()
+    };
+
+    let lines = iterative_deepening(
+        &Board::new(),
+        NoTTable::default(),
+        PrevMoves::new(),
+        &mut SearchHistory::new(),
+        &SearchLimits::new_depth(3),
+        &options,
+        SearchControl::new(),
+    )
+    .unwrap();
+
+    assert_eq!(lines.len(), 3);
+    assert!(lines.windows(2).all(|l| l[0].score >= l[1].score));
+    assert_ne!(lines[0].best_move(), lines[1].best_move());
+    assert_ne!(lines[1].best_move(), lines[2].best_move());
+    assert_ne!(lines[0].best_move(), lines[2].best_move());
+}
+
+#[test]
+fn searchmoves_restricts_root_moves() {
+    use crate::tt::NoTTable;
+
+    crate::init();
+
+    let b = Board::new();
+    let searchmoves = vec![
+        Move::new_from_text("a2a3", &b).unwrap(),
+        Move::new_from_text("h2h4", &b).unwrap(),
+    ];
+
+    let limits = SearchLimits {
+        searchmoves: searchmoves.clone(),
+        ..SearchLimits::new_depth(3)
+    };
+
+    let options = SearchOptions {
+        multipv: 4,
+        ..Default::default()
+    };
+
+    let lines = iterative_deepening(
+        &b,
+        NoTTable::default(),
+        PrevMoves::new(),
+        &mut SearchHistory::new(),
+        &limits,
+        &options,
+        SearchControl::new(),
+    )
+    .unwrap();
+
+    assert_eq!(lines.len(), 2);
+    assert!(lines.iter().all(|l| searchmoves.contains(&l.best_move())));
+}
+
+#[test]
+fn mate_scores_are_reported_in_moves() {
+    use crate::tt::NoTTable;
+
+    crate::init();
+
+    assert_eq!(uci_score(35), "cp 35");
+    assert_eq!(uci_score(MATED - 1), "mate 1");
+    assert_eq!(uci_score(MATED - 3), "mate 2");
+    assert_eq!(uci_score(CHECKMATE + 2), "mate -1");
+    assert_eq!(uci_score(CHECKMATE + 4), "mate -2");
+
+    assert_eq!(ScoreBound::from_window(10, 0, 10), ScoreBound::Lower);
+    assert_eq!(ScoreBound::from_window(0, 0, 10), ScoreBound::Upper);
+    assert_eq!(ScoreBound::from_window(5, 0, 10), ScoreBound::Exact);
+
+    // back rank mate with Ra8#
+    let b = Board::new_fen("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1").unwrap();
+    let lines = iterative_deepening(
+        &b,
+        NoTTable::default(),
+        PrevMoves::new(),
+        &mut SearchHistory::new(),
+        &SearchLimits::new_depth(3),
+        &SearchOptions::default(),
+        SearchControl::new(),
+    )
+    .unwrap();
+
+    assert_eq!(lines[0].best_move().as_uci_string(), "a1a8");
+    assert_eq!(uci_score(lines[0].score), "mate 1");
+    assert_eq!(lines[0].bound, ScoreBound::Exact);
+}
+
+#[cfg(test)]
+fn test_searcher(limits: &SearchLimits) -> Searcher<crate::tt::NoTTable> {
+    test_searcher_with_tt(crate::tt::NoTTable::default(), limits)
+}
+
+#[cfg(test)]
+fn test_searcher_with_tt<T: TT>(tt: T, limits: &SearchLimits) -> Searcher<T> {
+    let options = SearchOptions::default();
+    let control = SearchControl::new();
+    let tm = TimeManager::new(limits, WHITE, &options, control.clone());
+
+    Searcher::new(
+        tt,
+        PrevMoves::new(),
+        tm,
+        limits,
+        &options,
+        control.node_counter(),
+    )
+}
+
+#[test]
+fn root_search_reports_window_failures() {
+    crate::init();
+
+    let limits = SearchLimits::new_depth(3);
+    let mut s = test_searcher(&limits);
+    let b = Board::new();
+
+    let (score, _) = s.root_pvs(&b, MIN_SCORE, MAX_SCORE, 3, &[]).unwrap();
+    assert!(matches!(
+        s.root_pvs(&b, score + 500, score + 600, 3, &[]),
+        Err(SearchError::FailLow)
+    ));
+    assert!(matches!(
+        s.root_pvs(&b, score - 600, score - 500, 3, &[]),
+        Err(SearchError::FailHigh)
+    ));
+    // the move that failed high is left in the pv table
+    assert!(is_legal(&b, s.pv_table.get(0)));
+
+    let mated =
+        Board::new_fen("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3").unwrap();
+    assert!(matches!(
+        s.root_pvs(&mated, MIN_SCORE, MAX_SCORE, 3, &[]),
+        Err(SearchError::NoMove)
+    ));
+
+    // the search still finds the same line through the aspiration windows
+    let lines = search_lines(&mut s, &b, 5, 1, &[]).unwrap();
+    let windowed = search_lines(&mut s, &b, 5, 1, &lines).unwrap();
+    assert_eq!(lines[0].score, windowed[0].score);
+    assert_eq!(windowed[0].bound, ScoreBound::Exact);
+}
+
+#[test]
+fn excluded_move_is_skipped_without_scoring_a_mate() {
+    crate::init();
+
+    let mut s = test_searcher(&SearchLimits::new_depth(4));
+
+    // the king is in check with a single way out
+    let b = Board::new_fen("8/8/8/8/8/1k6/r7/K7 w - - 0 1").unwrap();
+    s.init_search(&b, 4);
+
+    let only_move = Move::new_from_text("a1b1", &b).unwrap();
+    s.stack[0].excluded = Some(only_move);
+    assert_eq!(s.pvs(&b, -50, 50, 4), -50);
+
+    s.stack[0].excluded = None;
+    assert!(s.pvs(&b, -50, 50, 4) > CHECKMATE + MAX_DEPTH as i32);
+}
+
+#[test]
+fn shallow_pruning_respects_margins_and_mates() {
+    assert!(reverse_futility_cutoff(500, 100, 1));
+    assert!(!reverse_futility_cutoff(150, 100, 1));
+    assert!(!reverse_futility_cutoff(500, 100, RFP_MAX_DEPTH + 1));
+    assert!(!reverse_futility_cutoff(500, MATED - 5, 1));
+
+    assert!(is_futile(-500, 0, 1));
+    assert!(!is_futile(-100, 0, 1));
+    assert!(!is_futile(-500, 0, FP_MAX_DEPTH + 1));
+    assert!(!is_futile(-500, MATED - 5, 1));
+
+    crate::init();
+
+    let b = Board::new_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+    let quiet = Move::new_from_text("h1h2", &b).unwrap();
+    let check = Move::new_from_text("h1h8", &b).unwrap();
+    assert!(is_quiet_move(&b, quiet));
+    assert!(!is_quiet_move(&b, check));
+}
+
+#[test]
+fn probcut_cuts_when_a_capture_wins_big() {
+    crate::init();
+
+    let mut s = test_searcher(&SearchLimits::new_depth(6));
+
+    // the black queen hangs to the rook
+    let b = Board::new_fen("4k3/8/8/8/3q4/8/8/3RK3 w - - 0 1").unwrap();
+    s.init_search(&b, 6);
+    let static_eval = eval(&b, s.c_mul);
+
+    assert!(s.probcut(&b, static_eval, 0, 6));
+    assert!(!s.probcut(&b, static_eval, 2000, 6));
+    assert!(!s.probcut(&b, static_eval, 0, PROBCUT_MIN_DEPTH - 1));
+}
+
+#[test]
+fn mate_search_stops_with_the_full_mating_line() {
+    use crate::movegen::has_legal_move;
+    use crate::tt::TTable;
+
+    crate::init();
+
+    let b =
+        Board::new_fen("r1b1kb1r/pppp1ppp/5q2/4n3/3KP3/2N3PN/PPP4P/R1BQ1B1R b kq - 0 1").unwrap();
+    let limits = SearchLimits {
+        mate: Some(3),
+        ..Default::default()
+    };
+    let control = SearchControl::new();
+    let mut tt = TTable::new();
+
+    let lines = iterative_deepening(
+        &b,
+        &mut tt,
+        PrevMoves::new(),
+        &mut SearchHistory::new(),
+        &limits,
+        &SearchOptions::default(),
+        control.clone(),
+    )
+    .unwrap();
+
+    assert_eq!(uci_score(lines[0].score), "mate 3");
+    assert_eq!(lines[0].moves.len(), 5);
+
+    let end = lines[0].moves.iter().fold(b, |b, m| b.copy_make(*m));
+    assert!(is_in_check(&end) && !has_legal_move(&end));
+}
+
+#[test]
+fn staged_move_list_yields_every_move_once() {
+    use crate::move_list::{StackMoveList, MAX_MOVES};
+    use crate::movegen::gen_moves;
+    use crate::tt::TTable;
+
+    crate::init();
+
+    let limits = SearchLimits::new_depth(1);
+    let mut tt = TTable::new();
+
+    let fens = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "8/8/8/2k5/2pP4/8/B7/4K3 b - d3 0 3",
+        "4k3/8/8/8/8/8/4q3/R3K2R w KQ - 0 1",
+    ];
+
+    for fen in fens {
+        let b = Board::new_fen(fen).unwrap();
+        let mut s = test_searcher_with_tt(&mut tt, &limits);
+        s.init_search(&b, 1);
+
+        let mut expected = StackMoveList::<MAX_MOVES>::new();
+        gen_moves(&b, &mut expected, is_in_check(&b));
+        let mut expected: Vec<Move> = expected.collect();
+
+        // tt and killer moves from another position must never be handed out
+        let stale = Move::new(0, 63, KING as u32, 0, MoveType::Quiet);
+        s.tt.insert(b.hash(), EntryScore::new_pv(0, 0), Some(stale), 1);
+        s.history
+            .km
+            .add(Move::new(7, 56, KING as u32, 0, MoveType::Quiet), 1);
+
+        let mut ml = ScoredMoveList::new(&b, &s, 1, is_in_check(&b));
+        let mut staged = Vec::new();
+        while let Some(m) = ml.next_move(&b, &s) {
+            staged.push(m);
+        }
+
+        assert_eq!(staged.len(), expected.len(), "{fen}");
+        staged.sort_by_key(|m| m.as_uci_string());
+        expected.sort_by_key(|m| m.as_uci_string());
+        assert_eq!(staged, expected, "{fen}");
+
+        // a playable tt move comes first
+        let tt_move = expected[expected.len() / 2];
+        s.tt.insert(b.hash(), EntryScore::new_pv(0, 0), Some(tt_move), 1);
+        let mut ml = ScoredMoveList::new(&b, &s, 1, is_in_check(&b));
+        assert_eq!(ml.next_move(&b, &s), Some(tt_move), "{fen}");
+
+        // a playable killer comes before the other quiets
+        let is_other_quiet = |m: &&Move| m.move_type() == MoveType::Quiet && **m != tt_move;
+        if let Some(killer) = expected.iter().find(is_other_quiet) {
+            s.history.km.add(*killer, 1);
+            let mut ml = ScoredMoveList::new(&b, &s, 1, is_in_check(&b));
+            let moves: Vec<Move> = std::iter::from_fn(|| ml.next_move(&b, &s)).collect();
+            let first_quiet = moves.iter().find(is_other_quiet);
+            assert_eq!(first_quiet, Some(killer), "{fen}");
+        }
+    }
+}
+
+#[test]
+fn cutoffs_record_countermoves_and_continuation_history() {
+    crate::init();
+
+    let mut s = test_searcher(&SearchLimits::new_depth(6));
+
+    let start = Board::new_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+    let e4 = Move::new_from_text("e2e4", &start).unwrap();
+    let b = start.copy_make(e4);
+    let e5 = Move::new_from_text("e7e5", &b).unwrap();
+    let b = b.copy_make(e5);
+    let nf3 = Move::new_from_text("g1f3", &b).unwrap();
+    let a3 = Move::new_from_text("a2a3", &b).unwrap();
+
+    s.init_search(&start, 6);
+    s.stack[0].current = Some(e4);
+    s.push_ply();
+    s.stack[1].current = Some(e5);
+    s.push_ply();
+
+    assert_eq!(s.prev_move(1), Some(e5));
+    assert_eq!(s.prev_move(2), Some(e4));
+    assert_eq!(s.prev_move(3), None);
+
+    let mut quiets_tried = StackMoveList::<MAX_MOVES>::new();
+    quiets_tried.add_move(a3);
+    s.update_quiet_history(&b, nf3, 4, quiets_tried);
+
+    let bonus = history_bonus(4);
+    assert_eq!(s.history.cm.get(e5), Some(nf3));
+    assert_eq!(s.history.ch.get(e5, nf3), bonus);
+    assert_eq!(s.history.ch.get(e4, nf3), bonus);
+    assert_eq!(s.quiet_history(&b, nf3), 3 * bonus);
+    assert_eq!(s.quiet_history(&b, a3), -3 * bonus);
+
+    // the countermove is handed out with the killers, before the quiets are generated, at a
+    // depth without killers
+    let mut ml = ScoredMoveList::new(&b, &s, 1, false);
+    let moves: Vec<Move> = std::iter::from_fn(|| ml.next_move(&b, &s)).collect();
+    assert_eq!(moves[0], nf3);
+
+    // after a null move there is no move to counter
+    s.stack[1].current = None;
+    assert_eq!(s.prev_move(1), None);
+    assert_eq!(s.quiet_history(&b, nf3), 2 * bonus);
+}
+
+#[test]
+fn check_evasions_in_qsearch_learn_from_the_capture_played() {
+    crate::init();
+
+    let mut s = test_searcher(&SearchLimits::new_depth(1));
+
+    // taking the knight checks, and the evasions are searched by pvs
+    let b = Board::new_fen("n6k/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+    let capture = Move::new_from_text("a1a8", &b).unwrap();
+    let unrelated = Move::new_from_text("e1e2", &b).unwrap();
+    s.init_search(&b, 1);
+    s.stack[0].current = Some(unrelated);
+
+    // white needs far more than the knight, so any evasion cuts
+    s.q_search(&b, 5000, 5001);
+
+    assert_eq!(s.stack[0].current, Some(capture));
+    assert!(s.history.cm.get(capture).is_some());
+    assert_eq!(s.history.cm.get(unrelated), None);
+}
+
+#[test]
+fn node_limit_counts_the_nodes_of_every_thread() {
+    crate::init();
+
+    let control = SearchControl::new();
+    let limits = SearchLimits {
+        nodes: Some(200_000),
+        ..Default::default()
+    };
+
+    lazy_smp(
+        &Board::new(),
+        SmpTTable::with_size_mb(16),
+        PrevMoves::new(),
+        &mut SearchHistory::new(),
+        &limits,
+        &SearchOptions::default(),
+        control.clone(),
+        4,
+    )
+    .unwrap();
+
+    // the other threads can each be a flush behind, but not a whole node limit
+    assert!(control.nodes() < 200_000 + 3 * 0x1000);
+}
+
+#[test]
+fn weakened_search_shows_only_the_lines_asked_for() {
+    crate::init();
+
+    let limits = SearchLimits::new_depth(1);
+    let options = SearchOptions {
+        skill_level: 0,
+        ..Default::default()
+    };
+    let control = SearchControl::new();
+    let tm = TimeManager::new(&limits, WHITE, &options, control.clone());
+    let mut s = Searcher::new(
+        crate::tt::NoTTable::default(),
+        PrevMoves::new(),
+        tm,
+        &limits,
+        &options,
+        control.node_counter(),
+    );
+
+    let multipv = Skill::new(&options).multipv(options.multipv);
+    let lines = search_lines(&mut s, &Board::new(), 1, multipv, &[]).unwrap();
+
+    assert_eq!(lines.len(), 4);
+    assert_eq!(s.info_lines, 1);
+}
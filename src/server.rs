@@ -7,20 +7,21 @@ use crate::{
     move_list::{StackMoveList, MAX_MOVES},
     movegen::{gen_moves, is_in_check, is_legal_move, moved_into_check},
     moves::{Move, PrevMoves},
-    tt::NoTTable,
     uci::{Uci, UciCommand},
 };
 use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::post, Router};
 use tokio::sync::Mutex;
 
+#[allow(unused)]
 struct Arbiter<T: Uci> {
     bot: T,
     board: Board,
     prev_moves: PrevMoves,
 }
 
+#[allow(unused)]
 impl<T: Uci> Arbiter<T> {
-    pub fn new() -> Arbiter<GameState<NoTTable>> {
+    pub fn new() -> Arbiter<GameState> {
         Arbiter {
             bot: GameState::new_no_tt(),
             board: Board::new(),
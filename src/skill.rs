@@ -0,0 +1,138 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::eval::PAWN_VALUE;
+use crate::searcher::{PvLine, SearchOptions};
+use crate::time_manager::SearchLimits;
+
+pub const MAX_SKILL_LEVEL: usize = 20;
+// rough elo range covered by the skill levels, level 0 plays at about MIN_ELO and level 20 is
+// full strength
+pub const MIN_ELO: usize = 1000;
+pub const MAX_ELO: usize = 2600;
+// a weakened search always looks at this many root moves so it has something to pick from
+const SKILL_MULTIPV: usize = 4;
+// node cap of level 0, doubled every two levels. Large enough for a depth 1 search to finish
+const MIN_NODES_LOG2: usize = 12;
+
+// weakens play by capping the search and picking among the near-best root moves
+#[derive(Debug, Clone)]
+pub struct Skill {
+    level: usize,
+    rng: ChaCha8Rng,
+}
+
+impl Skill {
+    // UCI_LimitStrength takes priority over the skill level
+    pub fn new(options: &SearchOptions) -> Skill {
+        let level = if options.limit_strength {
+            elo_to_level(options.elo)
+        } else {
+            options.skill_level.min(MAX_SKILL_LEVEL)
+        };
+
+        Skill::with_rng(level, ChaCha8Rng::from_entropy())
+    }
+
+    pub fn with_rng(level: usize, rng: ChaCha8Rng) -> Skill {
+        Skill { level, rng }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.level < MAX_SKILL_LEVEL
+    }
+
+    // lower levels search shallower and fewer nodes, the caps never loosen the given limits
+    pub fn limit(&self, limits: &SearchLimits) -> SearchLimits {
+        if !self.enabled() {
+            return limits.clone();
+        }
+
+        let max_depth = 1 + self.level / 2;
+        let max_nodes = 1 << (MIN_NODES_LOG2 + self.level / 2);
+
+        SearchLimits {
+            depth: Some(limits.depth.map_or(max_depth, |depth| depth.min(max_depth))),
+            nodes: Some(limits.nodes.map_or(max_nodes, |nodes| nodes.min(max_nodes))),
+            ..limits.clone()
+        }
+    }
+
+    pub fn multipv(&self, multipv: usize) -> usize {
+        if self.enabled() {
+            multipv.max(SKILL_MULTIPV)
+        } else {
+            multipv
+        }
+    }
+
+    // moves the picked line to the front, every line gets a random bonus that grows as the level
+    // drops and worse lines are penalised less, so weak levels still mostly play sensible moves
+    pub fn pick_line(&mut self, mut lines: Vec<PvLine>) -> Vec<PvLine> {
+        if !self.enabled() || lines.len() < 2 {
+            return lines;
+        }
+
+        let weakness = 120 - 2 * self.level as i64;
+        let top = lines[0].score as i64;
+        let spread = (top - lines[lines.len() - 1].score as i64).min(PAWN_VALUE as i64);
+
+        let mut best = (0, i64::MIN);
+        for (i, line) in lines.iter().enumerate() {
+            let score = line.score as i64;
+            let push = (weakness * (top - score) + spread * self.rng.gen_range(0..weakness)) / 128;
+
+            if score + push > best.1 {
+                best = (i, score + push);
+            }
+        }
+
+        lines[..=best.0].rotate_right(1);
+        lines
+    }
+}
+
+pub fn elo_to_level(elo: usize) -> usize {
+    let elo = elo.clamp(MIN_ELO, MAX_ELO);
+    (elo - MIN_ELO) * MAX_SKILL_LEVEL / (MAX_ELO - MIN_ELO)
+}
+
+#[test]
+fn skill_picks_near_best_lines() {
+    use crate::moves::{Move, MoveType};
+    use crate::searcher::ScoreBound;
+
+    let line = |score, from| PvLine {
+        score,
+        bound: ScoreBound::Exact,
+        moves: vec![Move::new(from, from + 8, 0, 0, MoveType::Quiet)],
+    };
+    let lines = vec![line(50, 8), line(30, 9), line(-400, 10), line(-900, 11)];
+
+    let mut full = Skill::with_rng(MAX_SKILL_LEVEL, ChaCha8Rng::seed_from_u64(0));
+    assert_eq!(full.pick_line(lines.clone()), lines);
+    assert_eq!(
+        full.limit(&SearchLimits::default()),
+        SearchLimits::default()
+    );
+
+    let weak = Skill::with_rng(0, ChaCha8Rng::seed_from_u64(0));
+    let limits = weak.limit(&SearchLimits::new_depth(20));
+    assert_eq!(limits.depth, Some(1));
+    assert!(limits.nodes.is_some());
+
+    let mut mid = Skill::with_rng(MAX_SKILL_LEVEL / 2, ChaCha8Rng::seed_from_u64(0));
+    let mut picked = [0; 4];
+    for _ in 0..1000 {
+        let res = mid.pick_line(lines.clone());
+        let i = lines.iter().position(|l| *l == res[0]).unwrap();
+        picked[i] += 1;
+        assert_eq!(res.len(), lines.len());
+    }
+    assert!(picked[1] > 0);
+    assert_eq!(picked[3], 0);
+
+    assert_eq!(elo_to_level(0), 0);
+    assert_eq!(elo_to_level(MAX_ELO), MAX_SKILL_LEVEL);
+    assert!(elo_to_level(1800) < elo_to_level(2200));
+}
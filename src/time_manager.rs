@@ -0,0 +1,287 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::board::WHITE;
+use crate::eval::MATED;
+use crate::moves::Move;
+use crate::searcher::{SearchOptions, MAX_DEPTH};
+
+// time kept in reserve for communication lag between the engine and the gui
+pub const DEFAULT_MOVE_OVERHEAD_MS: u128 = 10;
+pub const MAX_MOVE_OVERHEAD_MS: u128 = 5000;
+// assume this many moves are left in the game when no movestogo is given
+const DEFAULT_MOVES_TO_GO: u128 = 30;
+// how many times over the soft limit the search is allowed to run
+const HARD_LIMIT_MUL: u128 = 3;
+// when pondering some of the search happens on the opponent's time, so more time can be used
+const PONDER_BONUS_DIV: u128 = 4;
+// a fail low at the root means the best move is in doubt, so the soft limit grows by this fraction
+const FAIL_LOW_BONUS_DIV: u128 = 2;
+
+// the limits of a search, mostly from the uci go command. Times are in ms
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SearchLimits {
+    pub wtime: Option<u128>,
+    pub btime: Option<u128>,
+    pub winc: Option<u128>,
+    pub binc: Option<u128>,
+    pub movestogo: Option<u128>,
+    pub movetime: Option<u128>,
+    pub depth: Option<usize>,
+    pub nodes: Option<usize>,
+    // stop as soon as a mate in at most this many moves is found
+    pub mate: Option<usize>,
+    pub infinite: bool,
+    pub ponder: bool,
+    // only these root moves are searched, all of them if empty
+    pub searchmoves: Vec<Move>,
+}
+
+impl SearchLimits {
+    pub fn new_movetime(movetime: u128) -> SearchLimits {
+        SearchLimits {
+            movetime: Some(movetime),
+            ..Default::default()
+        }
+    }
+
+    pub fn new_depth(depth: usize) -> SearchLimits {
+        SearchLimits {
+            depth: Some(depth),
+            ..Default::default()
+        }
+    }
+
+    fn time_and_inc(&self, ctm: usize) -> (Option<u128>, u128) {
+        if ctm == WHITE {
+            (self.wtime, self.winc.unwrap_or(0))
+        } else {
+            (self.btime, self.binc.unwrap_or(0))
+        }
+    }
+}
+
+// flags shared between the uci input thread and a running search
+#[derive(Debug, Clone, Default)]
+pub struct SearchControl {
+    stop: Arc<AtomicBool>,
+    pondering: Arc<AtomicBool>,
+    searching: Arc<AtomicBool>,
+    // nodes searched by every thread of the current (or last) search
+    nodes: Arc<AtomicUsize>,
+}
+
+impl SearchControl {
+    pub fn new() -> SearchControl {
+        SearchControl::default()
+    }
+
+    // called before a search is handed to the engine thread, so a stop sent straight after the go
+    // command is not lost
+    pub fn new_search(&self, ponder: bool) {
+        self.stop.store(false, Ordering::SeqCst);
+        self.pondering.store(ponder, Ordering::SeqCst);
+        self.searching.store(true, Ordering::SeqCst);
+    }
+
+    pub fn finish_search(&self) {
+        self.searching.store(false, Ordering::SeqCst);
+    }
+
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    pub fn ponderhit(&self) {
+        self.pondering.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.pondering.load(Ordering::Relaxed)
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.searching.load(Ordering::SeqCst)
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.nodes.load(Ordering::Relaxed)
+    }
+
+    // resets the node count for a new search and returns the counter the searchers add to
+    pub fn node_counter(&self) -> Arc<AtomicUsize> {
+        self.nodes.store(0, Ordering::Relaxed);
+        self.nodes.clone()
+    }
+
+    // an infinite or ponder search must not report a bestmove until the gui says so
+    pub fn wait_for_release(&self, limits: &SearchLimits) {
+        while !self.is_stopped() && (limits.infinite || self.is_pondering()) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+// the soft limit is checked between iterations, the hard limit aborts the search itself
+#[derive(Debug, Clone)]
+pub struct TimeManager {
+    start: Instant,
+    soft_limit: Option<u128>,
+    hard_limit: Option<u128>,
+    max_nodes: Option<usize>,
+    max_depth: usize,
+    mate: Option<usize>,
+    control: SearchControl,
+}
+
+impl TimeManager {
+    pub fn new(
+        limits: &SearchLimits,
+        ctm: usize,
+        options: &SearchOptions,
+        control: SearchControl,
+    ) -> TimeManager {
+        let (soft_limit, hard_limit) = match (limits.movetime, limits.time_and_inc(ctm)) {
+            _ if limits.infinite => (None, None),
+            (Some(movetime), _) => {
+                let limit = movetime.saturating_sub(options.move_overhead).max(1);
+                (Some(limit), Some(limit))
+            }
+            (None, (Some(time), inc)) => {
+                let (mut soft, hard) =
+                    allocate_time(time, inc, limits.movestogo, options.move_overhead);
+
+                if options.ponder {
+                    soft = (soft + soft / PONDER_BONUS_DIV).min(hard);
+                }
+
+                (Some(soft), Some(hard))
+            }
+            _ => (None, None),
+        };
+
+        TimeManager {
+            start: Instant::now(),
+            soft_limit,
+            hard_limit,
+            max_nodes: limits.nodes,
+            max_depth: limits
+                .depth
+                .unwrap_or(MAX_DEPTH - 1)
+                .clamp(1, MAX_DEPTH - 1),
+            mate: limits.mate,
+            control,
+        }
+    }
+
+    pub fn elapsed_ms(&self) -> u128 {
+        self.start.elapsed().as_millis()
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    // repeated fail lows keep extending the soft limit, but never past the hard limit
+    pub fn extend_after_fail_low(&mut self) {
+        if let (Some(soft), Some(hard)) = (self.soft_limit, self.hard_limit) {
+            self.soft_limit = Some((soft + soft / FAIL_LOW_BONUS_DIV).min(hard));
+        }
+    }
+
+    // a mate search is done once the score proves a mate that is short enough
+    pub fn mate_found(&self, score: i32) -> bool {
+        self.mate
+            .is_some_and(|moves| score >= MATED + 1 - 2 * moves as i32)
+    }
+
+    pub fn out_of_nodes(&self, nodes: usize) -> bool {
+        self.max_nodes.is_some_and(|max| nodes >= max)
+    }
+
+    // checked while searching, once this is true the current iteration must be thrown away
+    pub fn hard_stop(&self, nodes: usize) -> bool {
+        self.control.is_stopped() || self.out_of_nodes(nodes) || self.past_limit(self.hard_limit)
+    }
+
+    // checked after an iteration has finished, stops a new iteration from being started
+    pub fn soft_stop(&self, nodes: usize) -> bool {
+        self.hard_stop(nodes) || self.past_limit(self.soft_limit)
+    }
+
+    // the clock does not apply while pondering, only once the gui sends a ponderhit
+    fn past_limit(&self, limit: Option<u128>) -> bool {
+        !self.control.is_pondering() && limit.is_some_and(|limit| self.elapsed_ms() >= limit)
+    }
+}
+
+// returns the (soft, hard) limits in ms for a side with time ms left on the clock
+fn allocate_time(
+    time: u128,
+    inc: u128,
+    movestogo: Option<u128>,
+    move_overhead: u128,
+) -> (u128, u128) {
+    let available = time.saturating_sub(move_overhead).max(1);
+    let moves_to_go = movestogo.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+
+    let soft = (available / moves_to_go + inc * 3 / 4)
+        .min(available / 2)
+        .max(1);
+    let hard = (soft * HARD_LIMIT_MUL).min(available * 3 / 4).max(soft);
+
+    (soft, hard)
+}
+
+#[test]
+fn time_manager_allocates_within_clock() {
+    let limits = SearchLimits {
+        wtime: Some(60000),
+        btime: Some(1000),
+        winc: Some(1000),
+        ..Default::default()
+    };
+
+    let options = SearchOptions::default();
+
+    let white = TimeManager::new(&limits, WHITE, &options, SearchControl::new());
+    let (soft, hard) = (white.soft_limit.unwrap(), white.hard_limit.unwrap());
+    assert!(soft < hard && hard < 60000);
+
+    let black = TimeManager::new(&limits, crate::board::BLACK, &options, SearchControl::new());
+    assert!(black.hard_limit.unwrap() < 1000);
+
+    let movetime = TimeManager::new(
+        &SearchLimits::new_movetime(500),
+        WHITE,
+        &options,
+        SearchControl::new(),
+    );
+    assert_eq!(movetime.soft_limit, movetime.hard_limit);
+
+    let infinite = TimeManager::new(
+        &SearchLimits {
+            infinite: true,
+            ..limits
+        },
+        WHITE,
+        &options,
+        SearchControl::new(),
+    );
+    assert!(infinite.soft_limit.is_none() && infinite.hard_limit.is_none());
+
+    let control = SearchControl::new();
+    control.new_search(true);
+    let pondering = TimeManager::new(&SearchLimits::new_movetime(1), WHITE, &options, control);
+    thread::sleep(Duration::from_millis(5));
+    assert!(!pondering.soft_stop(0));
+
+    pondering.control.ponderhit();
+    assert!(pondering.hard_stop(0));
+}
@@ -1,6 +1,7 @@
 use crate::eval::{CHECKMATE, MATED};
 use crate::moves::{Move, NULL_MOVE};
 use crate::searcher::{MAX_DEPTH, MIN_SCORE};
+use std::mem::size_of;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering::SeqCst;
 use std::sync::{Arc, RwLock};
@@ -11,13 +12,25 @@ pub const EMPTY_HASH: u64 = 0;
 pub const TTABLE_SIZE: usize = 1 << 24; // 2^20
 const TT_IDX_MASK: u64 = TTABLE_SIZE as u64 - 1;
 
+const MB: usize = 1 << 20;
+// number of entries looked at to estimate how full the table is
+const HASHFULL_SAMPLE: usize = 1000;
+pub const DEFAULT_HASH_MB: usize = TTABLE_SIZE * size_of::<TTEntry>() / MB;
+pub const MAX_HASH_MB: usize = 1 << 16;
+
 // pub const TTABLE_SIZE: usize = 65536; // 2^16
 // const TT_IDX_MASK: u64 = 0xFFFF;
 
 #[inline]
-fn tt_idx(hash: u64) -> usize {
+fn tt_idx(hash: u64, mask: u64) -> usize {
     // ((hash >> 32) & TT_IDX_MASK) as usize
-    (hash & TT_IDX_MASK) as usize
+    (hash & mask) as usize
+}
+
+// the largest power of 2 number of entries of type E that fit in mb megabytes
+fn entries_for_mb<E>(mb: usize) -> usize {
+    let entries = (mb.max(1) * MB / size_of::<E>()).max(1);
+    1 << entries.ilog2()
 }
 
 fn should_replace(_entry: TTEntry, _score: EntryScore) -> bool {
@@ -54,11 +67,27 @@ pub trait TT {
         self.set_entry(hash, TTEntry::new(hash, score, best, draft))
     }
 
+    // permille of the table in use, as reported to uci
+    fn hashfull(&self) -> usize {
+        0
+    }
+
     fn print_stats(&self) {
         println!("No TT stats to show");
     }
 }
 
+// estimates the permille of used entries from the first few entries of the table
+fn sample_hashfull(entries: impl ExactSizeIterator<Item = TTEntry>) -> usize {
+    let sample = entries.len().min(HASHFULL_SAMPLE);
+    let used = entries
+        .take(sample)
+        .filter(|entry| entry.hash != EMPTY_HASH)
+        .count();
+
+    used * 1000 / sample.max(1)
+}
+
 #[derive(Default, Debug, Copy, Clone)]
 pub struct NoTTable {
     entry: TTEntry,
@@ -77,6 +106,7 @@ impl TT for NoTTable {
 #[derive(Debug, Default)]
 pub struct TTable {
     ttable: Box<[TTEntry]>,
+    mask: u64,
     hits: AtomicUsize,
     misses: AtomicUsize,
     collisions: AtomicUsize,
@@ -86,8 +116,18 @@ pub struct TTable {
 
 impl TTable {
     pub fn new() -> TTable {
+        TTable::with_entries(TTABLE_SIZE)
+    }
+
+    pub fn with_size_mb(mb: usize) -> TTable {
+        TTable::with_entries(entries_for_mb::<TTEntry>(mb))
+    }
+
+    // size must be a power of 2
+    fn with_entries(size: usize) -> TTable {
         TTable {
-            ttable: vec![TTEntry::default(); TTABLE_SIZE].into_boxed_slice(),
+            ttable: vec![TTEntry::default(); size].into_boxed_slice(),
+            mask: size as u64 - 1,
             hits: AtomicUsize::new(0),
             misses: AtomicUsize::new(0),
             collisions: AtomicUsize::new(0),
@@ -99,11 +139,11 @@ impl TTable {
 
 impl TT for &mut TTable {
     fn get_entry(&self, hash: u64) -> TTEntry {
-        self.ttable[tt_idx(hash)]
+        self.ttable[tt_idx(hash, self.mask)]
     }
 
     fn set_entry(&mut self, hash: u64, entry: TTEntry) {
-        self.ttable[tt_idx(hash)] = entry;
+        self.ttable[tt_idx(hash, self.mask)] = entry;
     }
 
     fn clear(&mut self) {
@@ -112,6 +152,10 @@ impl TT for &mut TTable {
             .for_each(|entry| *entry = TTEntry::default())
     }
 
+    fn hashfull(&self) -> usize {
+        sample_hashfull(self.ttable.iter().copied())
+    }
+
     fn print_stats(&self) {
         let hits = self.hits.load(SeqCst);
         let misses = self.misses.load(SeqCst);
@@ -126,7 +170,7 @@ impl TT for &mut TTable {
 
         let total = hits + misses + cols;
         let percent = (hits as f64 / total as f64) * 100.0;
-        let capacity = (count as f64 / TTABLE_SIZE as f64) * 100.0;
+        let capacity = (count as f64 / self.ttable.len() as f64) * 100.0;
 
         let occ_inserts = (count as f64 / inserts as f64) * 100.0;
         println!(
@@ -147,26 +191,35 @@ impl TT for &mut TTable {
 
 pub struct SmpTTable {
     ttable: Box<[RwLock<TTEntry>]>,
+    mask: u64,
 }
 
 #[allow(unused)]
 impl SmpTTable {
     pub fn new() -> Arc<SmpTTable> {
+        SmpTTable::with_entries(TTABLE_SIZE)
+    }
+
+    pub fn with_size_mb(mb: usize) -> Arc<SmpTTable> {
+        SmpTTable::with_entries(entries_for_mb::<RwLock<TTEntry>>(mb))
+    }
+
+    // size must be a power of 2
+    fn with_entries(size: usize) -> Arc<SmpTTable> {
         Arc::new(SmpTTable {
-            ttable: (0..TTABLE_SIZE)
-                .map(|_| RwLock::new(TTEntry::default()))
-                .collect(),
+            ttable: (0..size).map(|_| RwLock::new(TTEntry::default())).collect(),
+            mask: size as u64 - 1,
         })
     }
 }
 
 impl TT for Arc<SmpTTable> {
     fn get_entry(&self, hash: u64) -> TTEntry {
-        *self.ttable[tt_idx(hash)].read().unwrap()
+        *self.ttable[tt_idx(hash, self.mask)].read().unwrap()
     }
 
     fn set_entry(&mut self, hash: u64, entry: TTEntry) {
-        *self.ttable[tt_idx(hash)].write().unwrap() = entry;
+        *self.ttable[tt_idx(hash, self.mask)].write().unwrap() = entry;
     }
 
     fn clear(&mut self) {
@@ -175,6 +228,10 @@ impl TT for Arc<SmpTTable> {
             .map(|rw| rw.write().unwrap())
             .for_each(|mut entry| *entry = TTEntry::default())
     }
+
+    fn hashfull(&self) -> usize {
+        sample_hashfull(self.ttable.iter().map(|rw| *rw.read().unwrap()))
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -207,6 +264,22 @@ impl EntryScore {
         matches!(self, EntryScore::PV(_))
     }
 
+    // the score the position is known to reach, exact and beta entries have one
+    pub fn lower_bound(self, ply: i32) -> Option<i32> {
+        match self {
+            PV(score) | Beta(score) => Some(adjust_retrieve(score, ply)),
+            Alpha(_) => None,
+        }
+    }
+
+    // the score the position is known not to exceed, exact and alpha entries have one
+    pub fn upper_bound(self, ply: i32) -> Option<i32> {
+        match self {
+            PV(score) | Alpha(score) => Some(adjust_retrieve(score, ply)),
+            Beta(_) => None,
+        }
+    }
+
     pub fn get_score(self, alpha: i32, beta: i32, ply: i32) -> Option<i32> {
         match self {
             PV(score) => Some(adjust_retrieve(score, ply)),
@@ -270,7 +343,7 @@ impl PerftTT {
     }
 
     pub fn get_count(&self, hash: u64, depth: u64) -> Option<u64> {
-        let entry = self.ttable[tt_idx(hash)];
+        let entry = self.ttable[tt_idx(hash, TT_IDX_MASK)];
 
         if entry.hash == hash && entry.depth == depth {
             Some(entry.count)
@@ -281,7 +354,7 @@ impl PerftTT {
 
     #[inline]
     pub fn store(&mut self, hash: u64, count: u64, depth: u64) {
-        let entry = &mut self.ttable[(hash & TT_IDX_MASK) as usize];
+        let entry = &mut self.ttable[tt_idx(hash, TT_IDX_MASK)];
         entry.update(hash, count, depth);
     }
 }
@@ -1,14 +1,59 @@
 use crate::board::Board;
 use crate::error::{InvalidUciCommand, SearchError};
+use crate::eval::eval_trace;
 use crate::game_state::{CanSearch, GameState};
 use crate::moves::{Move, PrevMoves};
-use crate::uci::UciCommand::{Go, IsReady, Position, Quit, UciInfo, UciNewGame};
+use crate::perft::HashPerft;
+use crate::searcher::SearchOptions;
+use crate::skill::{MAX_ELO, MAX_SKILL_LEVEL, MIN_ELO};
+use crate::time_manager::{
+    SearchControl, SearchLimits, DEFAULT_MOVE_OVERHEAD_MS, MAX_MOVE_OVERHEAD_MS,
+};
+use crate::tt::{DEFAULT_HASH_MB, MAX_HASH_MB};
+use crate::uci::UciCommand::{
+    Bench, Go, IsReady, Perft, PonderHit, Position, PrintBoard, PrintEval, Quit, SetOption, Stop,
+    UciInfo, UciNewGame,
+};
 use std::io;
 use std::io::BufRead;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub const AUTHOR: &str = "George";
 pub const BOT_NAME: &str = "RookNRoll";
 
+const GO_KEYWORDS: [&str; 12] = [
+    "wtime",
+    "btime",
+    "winc",
+    "binc",
+    "movestogo",
+    "movetime",
+    "depth",
+    "nodes",
+    "mate",
+    "infinite",
+    "ponder",
+    "searchmoves",
+];
+
+pub const MAX_THREADS: usize = 256;
+pub const MAX_MULTIPV: usize = 256;
+
+const BENCH_DEPTH: usize = 7;
+// a spread of openings, middlegames and endgames searched by the bench command
+const BENCH_FENS: [&str; 8] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP2BPPP/R2QKB1R w KQ - 0 8",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1",
+    "8/8/4k3/3p4/3P4/4K3/8/8 w - - 0 1",
+];
+
 pub enum UciCommand {
     UciNewGame,
     UciInfo,
@@ -17,8 +62,22 @@ pub enum UciCommand {
         fen: Option<String>,
         moves: Vec<String>,
     },
-    Go(String),
+    Go {
+        limits: Box<SearchLimits>,
+        searchmoves: Vec<String>,
+    },
+    SetOption {
+        name: String,
+        value: Option<String>,
+    },
+    Stop,
+    PonderHit,
     Quit,
+    // non standard commands for debugging
+    PrintBoard,
+    PrintEval,
+    Perft(usize),
+    Bench(usize),
 }
 
 impl UciCommand {
@@ -30,8 +89,15 @@ impl UciCommand {
             "uci" => UciInfo,
             "isready" => IsReady,
             "position" => UciCommand::new_pos_command(args)?,
-            "go" => Go(args.to_string()),
+            "go" => UciCommand::new_go_command(args)?,
+            "setoption" => UciCommand::new_set_option_command(args)?,
+            "stop" => Stop,
+            "ponderhit" => PonderHit,
             "quit" => Quit,
+            "d" => PrintBoard,
+            "eval" => PrintEval,
+            "perft" => Perft(parse_depth(args, None)?),
+            "bench" => Bench(parse_depth(args, Some(BENCH_DEPTH))?),
             _ => Err(InvalidUciCommand::UnknownCommand(line.into()))?,
         })
     }
@@ -46,8 +112,6 @@ impl UciCommand {
 
         let moves_str = pos_args.next();
 
-        dbg!(pos_str, moves_str);
-
         let fen = if pos_str.starts_with("startpos") {
             None
         } else if pos_str.starts_with("fen") {
@@ -64,32 +128,142 @@ impl UciCommand {
             ))?
         };
 
-        dbg!(&fen);
-
-        let moves: Vec<String> = if let Some(moves_str) = moves_str {
-            if moves_str.is_empty() {
-                Vec::new()
-            } else {
-                moves_str
-                    .trim()
-                    .split(' ')
-                    .map(|s| s.trim().into())
-                    .collect()
+        let moves = moves_str
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+
+        Ok(UciCommand::Position { fen, moves })
+    }
+
+    fn new_go_command(go_args: &str) -> Result<UciCommand, InvalidUciCommand> {
+        let mut limits = SearchLimits::default();
+        let mut searchmoves = Vec::new();
+        let mut args = go_args.split_whitespace().peekable();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or(InvalidUciCommand::InvalidGoCommand(go_args.into()))
+            };
+
+            match arg {
+                "wtime" => limits.wtime = Some(parse_ms(value()?, go_args)?),
+                "btime" => limits.btime = Some(parse_ms(value()?, go_args)?),
+                "winc" => limits.winc = Some(parse_ms(value()?, go_args)?),
+                "binc" => limits.binc = Some(parse_ms(value()?, go_args)?),
+                "movestogo" => limits.movestogo = Some(parse_ms(value()?, go_args)?),
+                "movetime" => limits.movetime = Some(parse_ms(value()?, go_args)?),
+                "depth" => limits.depth = Some(parse_arg(value()?, go_args)?),
+                "nodes" => limits.nodes = Some(parse_arg(value()?, go_args)?),
+                "mate" => limits.mate = Some(parse_arg(value()?, go_args)?),
+                "infinite" => limits.infinite = true,
+                "ponder" => limits.ponder = true,
+                // the moves are only read up to the next keyword
+                "searchmoves" => {
+                    while let Some(m) = args.next_if(|arg| !GO_KEYWORDS.contains(arg)) {
+                        searchmoves.push(m.to_string());
+                    }
+                }
+                // ignore anything that is not supported yet
+                _ => {}
             }
-        } else {
-            Vec::new()
-        };
+        }
 
-        dbg!(&moves);
+        Ok(Go {
+            limits: Box::new(limits),
+            searchmoves,
+        })
+    }
 
-        Ok(UciCommand::Position { fen, moves })
+    // option names can contain spaces, so everything between name and value is the name
+    fn new_set_option_command(option_args: &str) -> Result<UciCommand, InvalidUciCommand> {
+        let name_value = option_args
+            .trim()
+            .strip_prefix("name ")
+            .ok_or(InvalidUciCommand::InvalidOption(option_args.into()))?;
+
+        let (name, value) = match name_value.split_once(" value ") {
+            Some((name, value)) => (name, Some(value.trim().to_string())),
+            None => (name_value, None),
+        };
+
+        Ok(SetOption {
+            name: name.trim().to_string(),
+            value,
+        })
     }
 }
 
-pub trait Uci: CanSearch {
-    fn start(&mut self) {
+fn parse_arg<T: std::str::FromStr>(value: &str, go_args: &str) -> Result<T, InvalidUciCommand> {
+    value
+        .parse()
+        .map_err(|_| InvalidUciCommand::InvalidGoCommand(go_args.into()))
+}
+
+// the depth given to the debug commands, which must be at least 1
+fn parse_depth(args: &str, default: Option<usize>) -> Result<usize, InvalidUciCommand> {
+    let depth = match args.trim() {
+        "" => default,
+        depth => depth.parse().ok(),
+    };
+
+    depth
+        .filter(|depth| *depth > 0)
+        .ok_or(InvalidUciCommand::InvalidDebugCommand(args.trim().into()))
+}
+
+fn searchmoves_from_text(
+    searchmoves: &[String],
+    b: &Board,
+) -> Result<Vec<Move>, InvalidUciCommand> {
+    searchmoves
+        .iter()
+        .map(|m_str| Move::new_from_text(m_str, b))
+        .collect()
+}
+
+// some guis send negative times when the engine is over time, so treat them as 0
+fn parse_ms(value: &str, go_args: &str) -> Result<u128, InvalidUciCommand> {
+    parse_arg::<i64>(value, go_args).map(|ms| ms.max(0) as u128)
+}
+
+pub trait Uci: CanSearch + Sized {
+    // reads commands on this thread and runs everything else, including searches, on an engine
+    // thread so that stop, ponderhit, isready and quit are handled while searching. The first
+    // command may already have been read while picking the protocol
+    fn start(self, first_command: Option<String>)
+    where
+        Self: Send + 'static,
+    {
+        let control = self.get_search_control();
+        let (tx, rx) = mpsc::channel();
+
+        let engine = thread::spawn(move || {
+            let mut uci = self;
+            for command in rx {
+                let is_search = matches!(command, Go { .. } | Bench(_));
+
+                if let Some(out) = uci.do_command(command) {
+                    println!("{}", out);
+                }
+
+                if is_search {
+                    uci.get_search_control().finish_search();
+                }
+            }
+        });
+
+        let mut first_command = first_command;
+
         loop {
-            let command = match self.next_command() {
+            let next = match first_command.take() {
+                Some(line) => UciCommand::new(&line),
+                None => next_command(),
+            };
+
+            let command = match next {
                 Ok(command) => command,
                 Err(err) => {
                     eprintln!("Unknown Command: {err}");
@@ -97,15 +271,43 @@ pub trait Uci: CanSearch {
                 }
             };
 
-            if let Quit = command {
-                eprintln!("Bye");
-                return;
-            }
-
-            if let Some(out) = self.do_command(command) {
-                println!("{}", out);
+            match command {
+                Quit => break,
+                Stop => control.stop(),
+                PonderHit => control.ponderhit(),
+                IsReady if control.is_searching() => println!("readyok"),
+                Go {
+                    limits,
+                    searchmoves,
+                } => {
+                    control.new_search(limits.ponder);
+                    // the engine thread only stops if it has been dropped
+                    let go = Go {
+                        limits,
+                        searchmoves,
+                    };
+                    if tx.send(go).is_err() {
+                        break;
+                    }
+                }
+                Bench(depth) => {
+                    control.new_search(false);
+                    if tx.send(Bench(depth)).is_err() {
+                        break;
+                    }
+                }
+                command => {
+                    if tx.send(command).is_err() {
+                        break;
+                    }
+                }
             }
         }
+
+        control.stop();
+        drop(tx);
+        engine.join().expect("Engine thread panicked");
+        eprintln!("Bye");
     }
 
     fn do_command(&mut self, command: UciCommand) -> Option<String> {
@@ -117,23 +319,86 @@ pub trait Uci: CanSearch {
             UciInfo => Some(self.handle_uci_info()),
             IsReady => Some(self.handle_is_ready()),
             Position { fen, moves } => {
-                self.handle_position_command(fen, moves);
+                if let Err(err) = self.handle_position_command(fen, moves) {
+                    eprintln!("{err}");
+                }
+                None
+            }
+            SetOption { name, value } => {
+                if let Err(err) = self.set_option(&name, value.as_deref()) {
+                    eprintln!("{err}");
+                }
                 None
             }
-            Go(_) => self.handle_search().ok(),
+            Go {
+                limits,
+                searchmoves,
+            } => {
+                // a gui waits for a bestmove even if there is nothing to play
+                let res = self.handle_search(*limits, searchmoves);
+                Some(res.unwrap_or_else(|_| "bestmove 0000".into()))
+            }
+            PrintBoard => Some(self.handle_print_board()),
+            PrintEval => Some(eval_trace(self.get_board())),
+            Perft(depth) => {
+                HashPerft::new().perftree_root(depth, &self.get_board().to_fen(), None);
+                None
+            }
+            Bench(depth) => Some(self.handle_bench(depth)),
             _ => None,
         }
     }
 
-    fn next_command(&mut self) -> Result<UciCommand, InvalidUciCommand> {
-        let mut buffer = String::new();
-        let mut stdin = io::stdin().lock();
-        stdin.read_line(&mut buffer).expect("Uci input failed");
-        UciCommand::new(&buffer)
+    fn handle_print_board(&self) -> String {
+        let board = self.get_board();
+        format!(
+            "{board}\nFen: {}\nKey: {:016X}",
+            board.to_fen(),
+            board.hash()
+        )
+    }
+
+    // searches each bench position to a fixed depth from a new game, so the node count only
+    // changes when the search does. The games are played on a bench game of their own, which
+    // leaves the position, tt and history of the session as they were
+    fn handle_bench(&mut self, depth: usize) -> String {
+        let mut search_time = Duration::ZERO;
+        let mut nodes = 0;
+        let mut report = String::new();
+        let mut game = self.get_bench_game();
+
+        for (i, fen) in BENCH_FENS.iter().enumerate() {
+            let board = Board::new_fen(fen).expect("bench fens are valid");
+            game.new_game();
+            game.set_pos(board, PrevMoves::new());
+
+            // the result is not needed, only the nodes and time it took to get there
+            let start = Instant::now();
+            let _ = game.go(SearchLimits::new_depth(depth));
+            search_time += start.elapsed();
+            let position_nodes = game.get_search_control().nodes();
+            nodes += position_nodes;
+
+            // per position counts show which kind of position a search change affects
+            report += &format!(
+                "Position {}/{}: {position_nodes}\n",
+                i + 1,
+                BENCH_FENS.len()
+            );
+        }
+
+        let ms = search_time.as_millis().max(1);
+        format!(
+            "{report}Nodes searched: {nodes}\nTime (ms): {ms}\nNodes/second: {}",
+            nodes as u128 * 1000 / ms
+        )
     }
 
     fn handle_uci_info(&mut self) -> String {
-        format!("id name {AUTHOR}\nid author {BOT_NAME}\nuciok")
+        format!(
+            "id name {BOT_NAME}\nid author {AUTHOR}\n{}\nuciok",
+            options_info()
+        )
     }
 
     fn handle_new_game(&mut self) {
@@ -147,47 +412,339 @@ pub trait Uci: CanSearch {
 
     fn get_is_ready(&mut self) -> bool;
 
-    fn handle_position_command(&mut self, fen: Option<String>, moves: Vec<String>) {
-        let mut board = Board::new();
+    fn get_search_control(&self) -> SearchControl;
 
-        if let Some(fen) = fen {
-            match Board::new_fen(&fen) {
-                Ok(b) => board = b,
-                Err(err) => {
-                    eprintln!("Unkown fen: {fen}, {err}");
-                    return;
-                }
-            };
-        }
+    fn set_option(&mut self, name: &str, value: Option<&str>) -> Result<(), InvalidUciCommand>;
+
+    // the position is only changed if the fen and every move are valid
+    fn handle_position_command(
+        &mut self,
+        fen: Option<String>,
+        moves: Vec<String>,
+    ) -> Result<(), InvalidUciCommand> {
+        let mut board = match fen {
+            Some(fen) => {
+                Board::new_fen(&fen).map_err(|err| InvalidUciCommand::InvalidFen(fen, err))?
+            }
+            None => Board::new(),
+        };
 
         let mut prev_moves = PrevMoves::new();
 
         for m_str in moves {
-            let m = Move::new_from_text(&m_str, &board);
+            let m = Move::new_from_text(&m_str, &board)?;
             board = board.copy_make(m);
             prev_moves.add(board.hash());
         }
 
         self.set_pos(board, prev_moves);
+        Ok(())
     }
 
     fn set_pos(&mut self, board: Board, prev_moves: PrevMoves);
 
-    fn handle_search(&mut self) -> Result<String, SearchError> {
-        self.go()
-            .map(|(_, best_move)| format!("bestmove {}", best_move.as_uci_string()))
+    fn get_board(&self) -> &Board;
+
+    fn get_options(&self) -> &SearchOptions;
+
+    // an empty game with the same options and search control, for searches that must not touch
+    // this one
+    fn get_bench_game(&self) -> Self;
+
+    fn handle_search(
+        &mut self,
+        mut limits: SearchLimits,
+        searchmoves: Vec<String>,
+    ) -> Result<String, SearchError> {
+        // searching every move instead of the ones asked for would answer another question, so a
+        // bad searchmove means there is nothing to search
+        let res = match searchmoves_from_text(&searchmoves, self.get_board()) {
+            Ok(moves) => {
+                limits.searchmoves = moves;
+                self.go(limits.clone())
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                Err(SearchError::NoMove)
+            }
+        };
+        self.get_search_control().wait_for_release(&limits);
+
+        res.map(|lines| {
+            let chess960 = self.get_options().chess960;
+            let best_move = lines[0].best_move().as_uci_notation(chess960);
+
+            // the expected reply is the second move of the pv, if the pv got that far
+            match lines[0].moves.get(1) {
+                Some(ponder_move) if self.get_options().ponder => {
+                    format!(
+                        "bestmove {best_move} ponder {}",
+                        ponder_move.as_uci_notation(chess960)
+                    )
+                }
+                _ => format!("bestmove {best_move}"),
+            }
+        })
     }
 }
 
-impl<T> Uci for GameState<T>
-where
-    GameState<T>: CanSearch,
-{
+fn options_info() -> String {
+    [
+        format!("option name Hash type spin default {DEFAULT_HASH_MB} min 1 max {MAX_HASH_MB}"),
+        format!("option name Threads type spin default 1 min 1 max {MAX_THREADS}"),
+        "option name Clear Hash type button".to_string(),
+        format!("option name MultiPV type spin default 1 min 1 max {MAX_MULTIPV}"),
+        "option name Ponder type check default false".to_string(),
+        "option name UCI_Chess960 type check default false".to_string(),
+        format!("option name Skill Level type spin default {MAX_SKILL_LEVEL} min 0 max {MAX_SKILL_LEVEL}"),
+        "option name UCI_LimitStrength type check default false".to_string(),
+        format!("option name UCI_Elo type spin default {MAX_ELO} min {MIN_ELO} max {MAX_ELO}"),
+        format!(
+            "option name Move Overhead type spin default {DEFAULT_MOVE_OVERHEAD_MS} min 0 max {MAX_MOVE_OVERHEAD_MS}"
+        ),
+    ]
+    .join("\n")
+}
+
+// parses a spin option value, rejecting anything outside of the advertised range
+fn parse_spin<T: std::str::FromStr + PartialOrd>(
+    name: &str,
+    value: Option<&str>,
+    min: T,
+    max: T,
+) -> Result<T, InvalidUciCommand> {
+    value
+        .and_then(|v| v.parse().ok())
+        .filter(|v| *v >= min && *v <= max)
+        .ok_or(InvalidUciCommand::InvalidOptionValue(
+            name.into(),
+            value.unwrap_or_default().into(),
+        ))
+}
+
+fn parse_check(name: &str, value: Option<&str>) -> Result<bool, InvalidUciCommand> {
+    match value {
+        Some("true") => Ok(true),
+        Some("false") => Ok(false),
+        _ => Err(InvalidUciCommand::InvalidOptionValue(
+            name.into(),
+            value.unwrap_or_default().into(),
+        )),
+    }
+}
+
+// none at the end of the input
+pub fn read_line() -> io::Result<Option<String>> {
+    let mut buffer = String::new();
+
+    match io::stdin().lock().read_line(&mut buffer)? {
+        0 => Ok(None),
+        _ => Ok(Some(buffer)),
+    }
+}
+
+fn next_command() -> Result<UciCommand, InvalidUciCommand> {
+    // treat the end of input as a quit, otherwise the loop would spin forever
+    match read_line() {
+        Ok(Some(line)) => UciCommand::new(&line),
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            Err(InvalidUciCommand::UnknownCommand(err.to_string()))
+        }
+        _ => Ok(Quit),
+    }
+}
+
+impl Uci for GameState {
     fn get_is_ready(&mut self) -> bool {
         self.is_ready()
     }
 
+    fn get_search_control(&self) -> SearchControl {
+        self.search_control()
+    }
+
     fn set_pos(&mut self, board: Board, prev_moves: PrevMoves) {
         self.set_position(board, prev_moves)
     }
+
+    fn get_board(&self) -> &Board {
+        self.board()
+    }
+
+    fn get_options(&self) -> &SearchOptions {
+        self.options()
+    }
+
+    fn get_bench_game(&self) -> GameState {
+        self.bench_game()
+    }
+
+    fn set_option(&mut self, name: &str, value: Option<&str>) -> Result<(), InvalidUciCommand> {
+        match name.to_lowercase().as_str() {
+            "hash" => self.set_hash_size(parse_spin(name, value, 1, MAX_HASH_MB)?),
+            "threads" => self.set_threads(parse_spin(name, value, 1, MAX_THREADS)?),
+            "clear hash" => self.clear_hash(),
+            "multipv" => self.options_mut().multipv = parse_spin(name, value, 1, MAX_MULTIPV)?,
+            "ponder" => self.options_mut().ponder = parse_check(name, value)?,
+            "uci_chess960" => self.options_mut().chess960 = parse_check(name, value)?,
+            "skill level" => {
+                self.options_mut().skill_level = parse_spin(name, value, 0, MAX_SKILL_LEVEL)?
+            }
+            "uci_limitstrength" => self.options_mut().limit_strength = parse_check(name, value)?,
+            "uci_elo" => self.options_mut().elo = parse_spin(name, value, MIN_ELO, MAX_ELO)?,
+            "move overhead" => {
+                self.options_mut().move_overhead = parse_spin(name, value, 0, MAX_MOVE_OVERHEAD_MS)?
+            }
+            _ => Err(InvalidUciCommand::UnknownOption(name.into()))?,
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn debug_commands_parse_depth() {
+    assert!(matches!(UciCommand::new("perft 5\n"), Ok(Perft(5))));
+    assert!(matches!(UciCommand::new("bench\n"), Ok(Bench(BENCH_DEPTH))));
+    assert!(matches!(UciCommand::new("bench 3\n"), Ok(Bench(3))));
+    assert!(UciCommand::new("perft\n").is_err());
+    assert!(UciCommand::new("perft 0\n").is_err());
+    assert!(matches!(UciCommand::new("d\n"), Ok(PrintBoard)));
+}
+
+#[test]
+fn go_command_parses_limits() {
+    let Ok(Go { limits, .. }) =
+        UciCommand::new("go wtime 300000 btime -20 winc 2000 binc 2000 movestogo 40 depth 12\n")
+    else {
+        panic!("go command did not parse");
+    };
+
+    assert_eq!(
+        *limits,
+        SearchLimits {
+            wtime: Some(300000),
+            btime: Some(0),
+            winc: Some(2000),
+            binc: Some(2000),
+            movestogo: Some(40),
+            depth: Some(12),
+            ..Default::default()
+        }
+    );
+
+    assert!(matches!(
+        UciCommand::new("go infinite"),
+        Ok(Go { limits, .. }) if limits.infinite
+    ));
+
+    let Ok(Go { searchmoves, .. }) = UciCommand::new("go searchmoves e2e4 d2d4 depth 5") else {
+        panic!("go command did not parse");
+    };
+    assert_eq!(searchmoves, ["e2e4", "d2d4"]);
+    assert!(matches!(
+        UciCommand::new("go mate 3"),
+        Ok(Go { limits, .. }) if limits.mate == Some(3)
+    ));
+    assert!(UciCommand::new("go movetime").is_err());
+    assert!(UciCommand::new("go nodes lots").is_err());
+}
+
+#[test]
+fn set_option_command_parses_name_and_value() {
+    let Ok(SetOption { name, value }) = UciCommand::new("setoption name Move Overhead value 50\n")
+    else {
+        panic!("setoption command did not parse");
+    };
+    assert_eq!(
+        (name.as_str(), value.as_deref()),
+        ("Move Overhead", Some("50"))
+    );
+
+    let Ok(SetOption { name, value }) = UciCommand::new("setoption name Clear Hash") else {
+        panic!("setoption command did not parse");
+    };
+    assert_eq!((name.as_str(), value), ("Clear Hash", None));
+
+    assert!(UciCommand::new("setoption Hash value 16").is_err());
+}
+
+#[test]
+fn invalid_searchmoves_are_not_searched() {
+    crate::init();
+
+    let mut game = GameState::new_no_tt();
+    let go = |searchmoves: &[&str]| Go {
+        limits: Box::new(SearchLimits::new_depth(2)),
+        searchmoves: searchmoves.iter().map(|m| m.to_string()).collect(),
+    };
+
+    assert_eq!(game.do_command(go(&["e2e5"])), Some("bestmove 0000".into()));
+    assert_eq!(
+        game.do_command(go(&["e2e4", "e2e5"])),
+        Some("bestmove 0000".into())
+    );
+    assert_eq!(game.do_command(go(&["e2e4"])), Some("bestmove e2e4".into()));
+
+    let board = Board::new();
+    assert!(matches!(
+        searchmoves_from_text(&["e2e5".into()], &board),
+        Err(InvalidUciCommand::IllegalMove(_))
+    ));
+    assert!(matches!(
+        searchmoves_from_text(&["e2".into()], &board),
+        Err(InvalidUciCommand::InvalidMove(_))
+    ));
+}
+
+#[test]
+fn bench_leaves_the_session_position_alone() {
+    crate::init();
+
+    let mut game = GameState::new_no_tt();
+    let Ok(position) = UciCommand::new("position startpos moves e2e4 c7c5 g1f3") else {
+        panic!("position command did not parse");
+    };
+    game.do_command(position);
+    let before = game.do_command(PrintBoard).unwrap();
+
+    let bench = game.do_command(Bench(1)).unwrap();
+    assert!(bench.contains("Nodes searched"));
+
+    let after = game.do_command(PrintBoard).unwrap();
+    assert_eq!(before, after);
+    assert!(after.contains("rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1"));
+}
+
+#[test]
+fn position_rejects_unplayable_fens() {
+    use crate::error::InvalidFenError;
+
+    crate::init();
+
+    let mut game = GameState::new_no_tt();
+    let start = *game.get_board();
+
+    // white could take the king straight away
+    assert!(matches!(
+        game.handle_position_command(Some("4k2R/8/8/8/8/8/8/4K3 w - - 0 1".into()), vec![]),
+        Err(InvalidUciCommand::InvalidFen(
+            _,
+            InvalidFenError::OppInCheck
+        ))
+    ));
+    assert_eq!(*game.get_board(), start);
+
+    // the black pawns run off the end of the rank
+    assert!(matches!(
+        game.handle_position_command(
+            Some("rnbqkbnr/ppppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".into()),
+            vec![]
+        ),
+        Err(InvalidUciCommand::InvalidFen(
+            _,
+            InvalidFenError::InvalidPieces
+        ))
+    ));
+    assert_eq!(*game.get_board(), start);
 }
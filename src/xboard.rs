@@ -0,0 +1,489 @@
+use crate::board::{Board, BLACK, WHITE};
+use crate::error::InvalidXBoardCommand;
+use crate::game_state::{CanSearch, GameState};
+use crate::movegen::{has_legal_move, is_in_check};
+use crate::moves::{Move, PrevMoves};
+use crate::searcher::InfoOutput;
+use crate::time_manager::SearchLimits;
+use crate::tt::MAX_HASH_MB;
+use crate::uci::{read_line, BOT_NAME, MAX_THREADS};
+use crate::xboard::XBoardCommand::{
+    Cores, Force, GameResult, Go, Ignored, Level, Memory, MoveNow, New, OppTime, Ping, Post,
+    Protover, Quit, Remove, SearchDepth, SearchTime, SetBoard, Time, Undo, UserMove,
+};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+// a clock set by the level command. Times are in ms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeControl {
+    // moves to play before the clock is topped up again, 0 if the base time is for the whole game
+    pub moves: u128,
+    pub base: u128,
+    pub inc: u128,
+}
+
+impl TimeControl {
+    // level MPS BASE INC, where the base is in minutes or minutes:seconds and the increment in
+    // seconds
+    fn new(args: &str) -> Option<TimeControl> {
+        let mut args = args.split_whitespace();
+        let moves = args.next()?.parse().ok()?;
+
+        let base = args.next()?;
+        let base = match base.split_once(':') {
+            Some((mins, secs)) => {
+                mins.parse::<u128>().ok()? * 60000 + secs.parse::<u128>().ok()? * 1000
+            }
+            None => parse_secs(base)? * 60,
+        };
+
+        let inc = parse_secs(args.next()?)?;
+
+        Some(TimeControl { moves, base, inc })
+    }
+}
+
+impl Default for TimeControl {
+    fn default() -> Self {
+        TimeControl {
+            moves: 40,
+            base: 5 * 60000,
+            inc: 0,
+        }
+    }
+}
+
+pub enum XBoardCommand {
+    Protover(usize),
+    New,
+    Force,
+    Go,
+    MoveNow,
+    UserMove(String),
+    Level(TimeControl),
+    SearchTime(u128),
+    SearchDepth(usize),
+    Time(u128),
+    OppTime(u128),
+    Undo,
+    Remove,
+    SetBoard(String),
+    GameResult,
+    Ping(String),
+    Post(bool),
+    Memory(usize),
+    Cores(usize),
+    Quit,
+    // commands that need no reply and change nothing here
+    Ignored,
+}
+
+impl XBoardCommand {
+    pub fn new(line: &str) -> Result<XBoardCommand, InvalidXBoardCommand> {
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+        let invalid = || InvalidXBoardCommand::InvalidArgs(line.into());
+
+        Ok(match command {
+            "protover" => Protover(args.parse().map_err(|_| invalid())?),
+            "new" => New,
+            "force" => Force,
+            "go" => Go,
+            "?" => MoveNow,
+            "usermove" => UserMove(args.into()),
+            "level" => Level(TimeControl::new(args).ok_or_else(invalid)?),
+            "st" => SearchTime(parse_secs(args).ok_or_else(invalid)?),
+            "sd" => SearchDepth(args.parse().ok().filter(|d| *d > 0).ok_or_else(invalid)?),
+            "time" => Time(parse_centis(args).ok_or_else(invalid)?),
+            "otim" => OppTime(parse_centis(args).ok_or_else(invalid)?),
+            "undo" => Undo,
+            "remove" => Remove,
+            "setboard" => SetBoard(args.into()),
+            "result" => GameResult,
+            "ping" => Ping(args.into()),
+            "post" => Post(true),
+            "nopost" => Post(false),
+            "memory" => Memory(args.parse().map_err(|_| invalid())?),
+            "cores" => Cores(args.parse().map_err(|_| invalid())?),
+            "quit" => Quit,
+            "xboard" | "accepted" | "rejected" | "random" | "computer" | "hard" | "easy"
+            | "name" | "rating" | "ics" => Ignored,
+            // guis that never got the usermove feature send moves on their own
+            _ if looks_like_move(command) => UserMove(command.into()),
+            _ => Err(InvalidXBoardCommand::UnknownCommand(line.into()))?,
+        })
+    }
+
+    // a search running when one of these arrives is stopped and its move is not played
+    fn ends_search(&self) -> bool {
+        matches!(self, New | Force | Undo | Remove | SetBoard(_) | GameResult)
+    }
+}
+
+fn looks_like_move(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    (4..=5).contains(&bytes.len())
+        && matches!(
+            bytes[..4],
+            [b'a'..=b'h', b'1'..=b'8', b'a'..=b'h', b'1'..=b'8']
+        )
+}
+
+// seconds, possibly fractional, in ms
+fn parse_secs(secs: &str) -> Option<u128> {
+    secs.parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(|secs| (secs * 1000.0) as u128)
+}
+
+// clocks are sent in centiseconds and go negative once a side is out of time
+fn parse_centis(centis: &str) -> Option<u128> {
+    centis
+        .parse::<i64>()
+        .ok()
+        .map(|centis| centis.max(0) as u128 * 10)
+}
+
+// plays games over xboard (CECP) with the same search as uci
+pub struct XBoard {
+    game: GameState,
+    // the position before each move played since the game started, so moves can be taken back
+    history: Vec<Board>,
+    prev_moves: PrevMoves,
+    force: bool,
+    engine_color: usize,
+    time_control: TimeControl,
+    move_time: Option<u128>,
+    max_depth: Option<usize>,
+    time: Option<u128>,
+    opp_time: Option<u128>,
+    // set by the input thread when a command arrives that the current search must not move after
+    discard: Arc<AtomicBool>,
+}
+
+impl XBoard {
+    pub fn new(mut game: GameState) -> XBoard {
+        // thinking output is off until the gui sends post
+        game.options_mut().output = InfoOutput::Silent;
+
+        XBoard {
+            game,
+            history: Vec::new(),
+            prev_moves: PrevMoves::new(),
+            force: false,
+            engine_color: BLACK,
+            time_control: TimeControl::default(),
+            move_time: None,
+            max_depth: None,
+            time: None,
+            opp_time: None,
+            discard: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // like uci, commands are read on this thread and run on an engine thread, so a move can be
+    // forced or a search abandoned while thinking
+    pub fn start(self) {
+        let control = self.game.search_control();
+        let discard = self.discard.clone();
+        let (tx, rx) = mpsc::channel();
+
+        let engine = thread::spawn(move || {
+            let mut xboard = self;
+            for command in rx {
+                match xboard.do_command(command) {
+                    Ok(Some(out)) => println!("{out}"),
+                    Ok(None) => {}
+                    Err(err) => println!("{err}"),
+                }
+            }
+        });
+
+        loop {
+            let command = match read_line() {
+                Ok(Some(line)) => XBoardCommand::new(&line),
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    Err(InvalidXBoardCommand::UnknownCommand(err.to_string()))
+                }
+                _ => Ok(Quit),
+            };
+
+            let command = match command {
+                Ok(command) => command,
+                Err(err) => {
+                    println!("{err}");
+                    continue;
+                }
+            };
+
+            match command {
+                Quit => break,
+                MoveNow => control.stop(),
+                command => {
+                    if command.ends_search() {
+                        discard.store(true, Ordering::SeqCst);
+                        control.stop();
+                    }
+
+                    if tx.send(command).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        control.stop();
+        drop(tx);
+        engine.join().expect("Engine thread panicked");
+        eprintln!("Bye");
+    }
+
+    pub fn do_command(
+        &mut self,
+        command: XBoardCommand,
+    ) -> Result<Option<String>, InvalidXBoardCommand> {
+        // any search this command was meant to stop has finished by now
+        if command.ends_search() {
+            self.discard.store(false, Ordering::SeqCst);
+        }
+
+        Ok(match command {
+            Protover(version) if version >= 2 => Some(features()),
+            New => {
+                self.new_game();
+                None
+            }
+            Force | GameResult => {
+                self.force = true;
+                None
+            }
+            Go => {
+                self.force = false;
+                self.engine_color = self.game.board().ctm();
+                self.think()
+            }
+            UserMove(text) => {
+                let m = Move::new_from_text(&text, self.game.board())
+                    .map_err(|_| InvalidXBoardCommand::IllegalMove(text))?;
+                self.make_move(m);
+                self.think()
+            }
+            // level and st replace each other
+            Level(time_control) => {
+                self.time_control = time_control;
+                self.move_time = None;
+                None
+            }
+            SearchTime(move_time) => {
+                self.move_time = Some(move_time);
+                None
+            }
+            SearchDepth(depth) => {
+                self.max_depth = Some(depth);
+                None
+            }
+            Time(time) => {
+                self.time = Some(time);
+                None
+            }
+            OppTime(time) => {
+                self.opp_time = Some(time);
+                None
+            }
+            Undo => {
+                self.undo();
+                None
+            }
+            Remove => {
+                self.undo();
+                self.undo();
+                None
+            }
+            SetBoard(fen) => {
+                let board =
+                    Board::new_fen(&fen).map_err(|_| InvalidXBoardCommand::IllegalPosition(fen))?;
+                self.history.clear();
+                self.prev_moves = PrevMoves::new();
+                self.game.set_position(board, self.prev_moves.clone());
+                None
+            }
+            Ping(n) => Some(format!("pong {n}")),
+            Post(post) => {
+                self.game.options_mut().output = if post {
+                    InfoOutput::XBoard
+                } else {
+                    InfoOutput::Silent
+                };
+                None
+            }
+            Memory(mb) => {
+                self.game.set_hash_size(mb.clamp(1, MAX_HASH_MB));
+                None
+            }
+            Cores(cores) => {
+                self.game.set_threads(cores.clamp(1, MAX_THREADS));
+                None
+            }
+            _ => None,
+        })
+    }
+
+    // the engine plays black in a new game, and the time control is kept
+    fn new_game(&mut self) {
+        self.game.new_game();
+        self.history.clear();
+        self.prev_moves = PrevMoves::new();
+        self.game
+            .set_position(Board::new(), self.prev_moves.clone());
+        self.force = false;
+        self.engine_color = BLACK;
+        self.max_depth = None;
+        self.time = None;
+        self.opp_time = None;
+    }
+
+    fn make_move(&mut self, m: Move) {
+        let board = *self.game.board();
+        let next = board.copy_make(m);
+
+        self.history.push(board);
+        self.prev_moves.add(next.hash());
+        self.game.set_position(next, self.prev_moves.clone());
+    }
+
+    fn undo(&mut self) {
+        let Some(board) = self.history.pop() else {
+            return;
+        };
+
+        self.prev_moves.remove(self.game.board().hash());
+        self.game.set_position(board, self.prev_moves.clone());
+    }
+
+    // searches and plays a move if it is the engine's turn
+    fn think(&mut self) -> Option<String> {
+        if self.force
+            || self.game.board().ctm() != self.engine_color
+            || self.discard.load(Ordering::SeqCst)
+        {
+            return None;
+        }
+
+        let control = self.game.search_control();
+        control.new_search(false);
+        let res = self.game.go(self.limits());
+        control.finish_search();
+
+        if self.discard.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        match res {
+            Ok(lines) => {
+                let m = lines[0].best_move();
+                self.make_move(m);
+                Some(format!("move {}", m.as_uci_notation(false)))
+            }
+            Err(_) if !has_legal_move(self.game.board()) => Some(self.game_over()),
+            Err(_) => None,
+        }
+    }
+
+    fn game_over(&self) -> String {
+        let board = self.game.board();
+
+        match (is_in_check(board), board.ctm()) {
+            (false, _) => "1/2-1/2 {Stalemate}",
+            (true, WHITE) => "0-1 {Black mates}",
+            _ => "1-0 {White mates}",
+        }
+        .into()
+    }
+
+    // the engine's clock is time and the gui's is otim, both fall back to the level base time
+    fn limits(&self) -> SearchLimits {
+        if let Some(move_time) = self.move_time {
+            return SearchLimits {
+                movetime: Some(move_time),
+                depth: self.max_depth,
+                ..Default::default()
+            };
+        }
+
+        let tc = self.time_control;
+        let time = self.time.unwrap_or(tc.base);
+        let opp_time = self.opp_time.unwrap_or(tc.base);
+        let (wtime, btime) = if self.engine_color == WHITE {
+            (time, opp_time)
+        } else {
+            (opp_time, time)
+        };
+
+        // each side has played half of the moves since the start of the game
+        let played = self.history.len() as u128 / 2;
+
+        SearchLimits {
+            wtime: Some(wtime),
+            btime: Some(btime),
+            winc: Some(tc.inc),
+            binc: Some(tc.inc),
+            movestogo: (tc.moves > 0).then(|| tc.moves - played % tc.moves),
+            depth: self.max_depth,
+            ..Default::default()
+        }
+    }
+}
+
+fn features() -> String {
+    format!(
+        "feature myname=\"{BOT_NAME}\" ping=1 setboard=1 usermove=1 playother=0 colors=0 \
+            sigint=0 sigterm=0 reuse=1 analyze=0 memory=1 smp=1 variants=\"normal\" done=1"
+    )
+}
+
+#[test]
+fn xboard_plays_and_takes_back_moves() {
+    crate::init();
+
+    let Ok(Level(tc)) = XBoardCommand::new("level 40 0:30 1.5\n") else {
+        panic!("level command did not parse");
+    };
+    assert_eq!(
+        tc,
+        TimeControl {
+            moves: 40,
+            base: 30000,
+            inc: 1500
+        }
+    );
+    assert!(matches!(XBoardCommand::new("time -50"), Ok(Time(0))));
+    assert!(matches!(XBoardCommand::new("e7e8q"), Ok(UserMove(m)) if m == "e7e8q"));
+    assert!(XBoardCommand::new("sd x").is_err());
+    assert!(XBoardCommand::new("fly").is_err());
+
+    let mut xboard = XBoard::new(GameState::new_no_tt());
+    xboard.do_command(New).unwrap();
+    xboard.do_command(SearchDepth(3)).unwrap();
+
+    let reply = xboard.do_command(UserMove("e2e4".into())).unwrap();
+    assert!(reply.is_some_and(|reply| reply.starts_with("move ")));
+    assert_eq!(xboard.history.len(), 2);
+
+    assert!(xboard.do_command(UserMove("e2e4".into())).is_err());
+
+    xboard.do_command(Remove).unwrap();
+    assert_eq!(*xboard.game.board(), Board::new());
+
+    // fool's mate, the engine has nothing left to play
+    let fen = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+    xboard.do_command(SetBoard(fen.into())).unwrap();
+    assert_eq!(
+        xboard.do_command(Go).unwrap().as_deref(),
+        Some("0-1 {Black mates}")
+    );
+}